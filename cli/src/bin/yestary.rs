@@ -0,0 +1,431 @@
+use std::{
+    fs::{
+        self,
+        File,
+    },
+    io::{
+        self,
+        Read,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    slice,
+    str::FromStr,
+};
+use aargvark::{
+    vark,
+    Aargvark,
+};
+use loga::{
+    ea,
+    fatal,
+    ResultContext,
+};
+use sequoia_openpgp::Cert;
+use shared::{
+    api::SerialApiError,
+    blind::{
+        commitment,
+        new_salt,
+        SerialBlinded,
+    },
+    chain::verify_chain,
+    hash::HashAlgorithm,
+    keys::{
+        compromise_filename,
+        meta_filename,
+        parse_compromise,
+        SerialKeyIndexEntry,
+        SerialKeyMeta,
+    },
+    roughtime::parse_public_key,
+    verify::{
+        parse_stamp,
+        verify_stamp,
+    },
+};
+
+const SUFFIX: &str = "notary_stamp";
+
+#[derive(Aargvark)]
+struct StampArgs {
+    /// Notary base url, like `https://yestary.example.com`
+    server: String,
+    files: Vec<PathBuf>,
+    /// Hash algorithm: sha256 (default), sha512, sha3_256 or blake3
+    algorithm: Option<String>,
+    /// Send a salted commitment instead of the hash, so the notary never sees it.
+    /// The salt is kept in the stamp file.
+    blind: Option<()>,
+    /// If a file was stamped before, get its earliest stamp instead of a new one
+    idempotent: Option<()>,
+}
+
+#[derive(Aargvark)]
+struct VerifyArgs {
+    file: PathBuf,
+    /// Defaults to the file path plus `.notary_stamp`
+    stamp: Option<PathBuf>,
+    /// Armored seal public key (cert). If not specified, it's fetched from
+    /// `server`.
+    key: Option<PathBuf>,
+    /// Seal key lifecycle metadata (`KEYID.meta.json`) for `key`.  If `key` is
+    /// fetched from `server` this is fetched too.
+    key_meta: Option<PathBuf>,
+    /// Signed compromise declaration (`KEYID.compromise`) for the key.  If not
+    /// specified and `server` is, it's fetched from `server` if it exists.
+    compromise: Option<PathBuf>,
+    /// Notary base url to fetch the seal key from
+    server: Option<String>,
+    /// Hex public keys of Roughtime servers you trust.  The stamp must carry an
+    /// attestation of its time from each.  Attestations by other servers aren't
+    /// checked.
+    roughtime_key: Option<Vec<String>>,
+}
+
+#[derive(Aargvark)]
+struct InspectArgs {
+    stamp: PathBuf,
+}
+
+#[derive(Aargvark)]
+struct ChainArgs {
+    /// Stamps to check, in any order
+    stamps: Vec<PathBuf>,
+    /// Armored seal public key (cert).  If specified, each stamp's signature is
+    /// checked too.
+    key: Option<PathBuf>,
+}
+
+#[derive(Aargvark)]
+struct KeysArgs {
+    /// Notary base url
+    server: String,
+}
+
+#[derive(Aargvark)]
+enum Args {
+    /// Hash files locally and get a `.notary_stamp` for each from the notary
+    Stamp(StampArgs),
+    /// Check a file against its stamp
+    Verify(VerifyArgs),
+    /// Show what's in a stamp
+    Inspect(InspectArgs),
+    /// Check that stamps form an unbroken chain with times that never decrease
+    Chain(ChainArgs),
+    /// List the notary's seal keys as markdown
+    Keys(KeysArgs),
+}
+
+fn hash_file(algorithm: HashAlgorithm, path: &Path) -> Result<String, loga::Error> {
+    let mut f = File::open(path).context_with("Error opening file", ea!(path = path.to_string_lossy().to_string()))?;
+    let mut hash = algorithm.hasher();
+    io::copy(&mut f, &mut hash).context_with("Error reading file", ea!(path = path.to_string_lossy().to_string()))?;
+    return Ok(hex::encode(hash.finalize()));
+}
+
+fn stamp_path(path: &Path) -> PathBuf {
+    let mut out = path.as_os_str().to_owned();
+    out.push(".");
+    out.push(SUFFIX);
+    return PathBuf::from(out);
+}
+
+fn main() {
+    fn inner() -> Result<(), loga::Error> {
+        match vark::<Args>() {
+            Args::Stamp(args) => {
+                let server = args.server.trim_end_matches('/');
+                let algorithm = match &args.algorithm {
+                    Some(a) => HashAlgorithm::from_str(
+                        a,
+                    ).map_err(|e| loga::err_with("Invalid hash algorithm", ea!(err = e)))?,
+                    None => HashAlgorithm::default(),
+                };
+                for path in args.files {
+                    let hash = hash_file(algorithm, &path)?;
+                    let salt = args.blind.map(|_| new_salt());
+                    let url = match &salt {
+                        Some(salt) => format!(
+                            "{}/api/stamp/{}?algorithm={}&blinded=true",
+                            server,
+                            commitment(algorithm, salt, &hash).unwrap(),
+                            algorithm
+                        ),
+                        None => format!("{}/api/stamp/{}?algorithm={}", server, hash, algorithm),
+                    };
+                    let url = match args.idempotent {
+                        Some(_) => format!("{}&idempotent=true", url),
+                        None => url,
+                    };
+                    let stamp =
+                        ureq::get(&url)
+                            .call()
+                            .map_err(|e| {
+                                let err = match e {
+                                    ureq::Error::Status(_, resp) => match resp.into_json::<SerialApiError>() {
+                                        Ok(e) => format!("{} ({:?})", e.message, e.code),
+                                        Err(e) => e.to_string(),
+                                    },
+                                    e => e.to_string(),
+                                };
+                                return loga::err_with(
+                                    "Error requesting stamp",
+                                    ea!(path = path.to_string_lossy().to_string(), err = err),
+                                );
+                            })?
+                            .into_string()
+                            .context("Error reading stamp response")?;
+                    let stamp = match salt {
+                        Some(salt) => serde_json::to_string(&SerialBlinded {
+                            salt: salt,
+                            stamp: stamp,
+                        }).unwrap(),
+                        None => stamp,
+                    };
+                    let out_path = stamp_path(&path);
+                    fs::write(
+                        &out_path,
+                        stamp,
+                    ).context_with("Error writing stamp", ea!(path = out_path.to_string_lossy().to_string()))?;
+                    println!("{}", out_path.to_string_lossy());
+                }
+            },
+            Args::Verify(args) => {
+                let stamp_path = args.stamp.unwrap_or_else(|| stamp_path(&args.file));
+                let stamp =
+                    fs::read(
+                        &stamp_path,
+                    ).context_with("Error reading stamp", ea!(path = stamp_path.to_string_lossy().to_string()))?;
+                let parsed = parse_stamp(&stamp).context("Error reading stamp")?;
+                let compromise = match (&args.compromise, &args.server) {
+                    (Some(path), _) => Some(
+                        fs::read(
+                            path,
+                        ).context_with(
+                            "Error reading compromise declaration",
+                            ea!(path = path.to_string_lossy().to_string()),
+                        )?,
+                    ),
+                    (None, Some(server)) => match ureq::get(
+                        &format!(
+                            "{}/api/key/{}",
+                            server.trim_end_matches('/'),
+                            compromise_filename(&parsed.issuer.to_hex().to_lowercase())
+                        ),
+                    ).call() {
+                        Ok(resp) => {
+                            let mut data = vec![];
+                            resp
+                                .into_reader()
+                                .read_to_end(&mut data)
+                                .context("Error reading compromise declaration response")?;
+                            Some(data)
+                        },
+                        Err(ureq::Error::Status(404, _)) => None,
+                        Err(e) => return Err(
+                            loga::err_with("Error requesting compromise declaration", ea!(err = e.to_string())),
+                        ),
+                    },
+                    (None, None) => None,
+                };
+                let (key_str, meta) = match (args.key, args.server) {
+                    (Some(key), _) => {
+                        let key_str =
+                            fs::read_to_string(
+                                &key,
+                            ).context_with("Error reading seal key", ea!(path = key.to_string_lossy().to_string()))?;
+                        let meta = match args.key_meta {
+                            Some(path) => Some(
+                                serde_json::from_slice::<SerialKeyMeta>(
+                                    &fs::read(
+                                        &path,
+                                    ).context_with(
+                                        "Error reading seal key metadata",
+                                        ea!(path = path.to_string_lossy().to_string()),
+                                    )?,
+                                ).context("Error parsing seal key metadata")?,
+                            ),
+                            None => None,
+                        };
+                        (key_str, meta)
+                    },
+                    (None, Some(server)) => {
+                        let server = server.trim_end_matches('/');
+                        let keyid = parsed.issuer.to_hex().to_lowercase();
+                        let key_str =
+                            ureq::get(&format!("{}/api/key/{}", server, keyid))
+                                .call()
+                                .map_err(|e| loga::err_with("Error requesting seal key", ea!(err = e.to_string())))?
+                                .into_string()
+                                .context("Error reading seal key response")?;
+                        let meta = match ureq::get(&format!("{}/api/key/{}", server, meta_filename(&keyid))).call() {
+                            Ok(resp) => Some(
+                                resp.into_json::<SerialKeyMeta>().context("Error reading seal key metadata response")?,
+                            ),
+                            // The server publishes no lifecycle for the key
+                            Err(ureq::Error::Status(404, _)) => None,
+                            Err(e) => return Err(
+                                loga::err_with("Error requesting seal key metadata", ea!(err = e.to_string())),
+                            ),
+                        };
+                        (key_str, meta)
+                    },
+                    (None, None) => return Err(loga::err("Need either a seal key or a server to get it from")),
+                };
+                let key =
+                    Cert::from_str(
+                        &key_str,
+                    ).map_err(|e| loga::err_with("Error parsing seal key", ea!(err = e.to_string())))?;
+                let verified =
+                    verify_stamp(
+                        &stamp,
+                        &hash_file(parsed.stamp.algorithm, &args.file)?,
+                        slice::from_ref(&key),
+                    ).context("Stamp verification failed")?;
+                if let Some(meta) = meta {
+                    meta.check(verified.stamp.stamp).context("Stamp verification failed")?;
+                }
+                if let Some(compromise) = compromise {
+                    let compromise =
+                        parse_compromise(&compromise, &key).context("Error reading compromise declaration")?;
+                    compromise.check(verified.stamp.stamp).context("Stamp verification failed")?;
+                    eprintln!(
+                        "Warning: seal key was compromised at {} ({}), after this stamp - treat it with caution",
+                        compromise.compromised.to_rfc3339(),
+                        compromise.reason
+                    );
+                }
+                let mut roughtime_keys = vec![];
+                for key in args.roughtime_key.unwrap_or_default() {
+                    roughtime_keys.push(
+                        parse_public_key(
+                            &key,
+                        ).ok_or_else(|| loga::err_with("Roughtime key must be 64 hex digits", ea!(key = key)))?,
+                    );
+                }
+                let attested = parsed.verify_roughtime(&roughtime_keys).context("Stamp verification failed")?;
+                for key in &roughtime_keys {
+                    if !attested.iter().any(|a| a.public_key == *key) {
+                        return Err(
+                            loga::err_with(
+                                "Stamp time isn't attested by a required Roughtime server",
+                                ea!(key = hex::encode(key)),
+                            ),
+                        );
+                    }
+                }
+                println!("Valid, stamped {}", verified.stamp.stamp.to_rfc3339());
+                for attestation in &attested {
+                    println!(
+                        "Time attested by Roughtime server {} ({})",
+                        attestation.server,
+                        hex::encode(attestation.public_key)
+                    );
+                }
+                for attestation in &verified.stamp.roughtime {
+                    if parse_public_key(&attestation.public_key).is_some_and(|k| roughtime_keys.contains(&k)) {
+                        continue;
+                    }
+                    println!(
+                        "Unchecked Roughtime attestation by {} ({}), pass --roughtime-key to trust it",
+                        attestation.server,
+                        attestation.public_key
+                    );
+                }
+            },
+            Args::Inspect(args) => {
+                let parsed =
+                    parse_stamp(
+                        &fs::read(
+                            &args.stamp,
+                        ).context_with("Error reading stamp", ea!(path = args.stamp.to_string_lossy().to_string()))?,
+                    ).context("Error reading stamp")?;
+                println!("{}", serde_json::to_string_pretty(&parsed.stamp).unwrap());
+                println!("issuer: {}", parsed.issuer.to_hex());
+                if let Some(salt) = &parsed.salt {
+                    println!("salt: {}", salt);
+                }
+            },
+            Args::Chain(args) => {
+                let key = match &args.key {
+                    Some(path) => Some(
+                        Cert::from_str(
+                            &fs::read_to_string(
+                                path,
+                            ).context_with("Error reading seal key", ea!(path = path.to_string_lossy().to_string()))?,
+                        ).map_err(|e| loga::err_with("Error parsing seal key", ea!(err = e.to_string())))?,
+                    ),
+                    None => None,
+                };
+                let mut stamps = vec![];
+                for path in &args.stamps {
+                    let parsed =
+                        parse_stamp(
+                            &fs::read(
+                                path,
+                            ).context_with("Error reading stamp", ea!(path = path.to_string_lossy().to_string()))?,
+                        ).context_with("Error reading stamp", ea!(path = path.to_string_lossy().to_string()))?;
+                    if let Some(key) = &key {
+                        parsed
+                            .verify(slice::from_ref(key))
+                            .context_with(
+                                "Stamp verification failed",
+                                ea!(path = path.to_string_lossy().to_string()),
+                            )?;
+                    }
+                    stamps.push((path, parsed));
+                }
+                stamps.sort_by_key(|(_, s)| s.stamp.serial);
+                let (paths, stamps): (Vec<_>, Vec<_>) = stamps.into_iter().unzip();
+                if let Err(e) = verify_chain(&stamps) {
+                    return Err(
+                        loga::err_with(
+                            "Chain verification failed",
+                            ea!(path = paths[e.index()].to_string_lossy().to_string(), err = e.to_string()),
+                        ),
+                    );
+                }
+                println!("Valid chain of {} stamps", stamps.len());
+            },
+            Args::Keys(args) => {
+                let index =
+                    ureq::get(&format!("{}/api/keys", args.server.trim_end_matches('/')))
+                        .call()
+                        .map_err(|e| loga::err_with("Error requesting key index", ea!(err = e.to_string())))?
+                        .into_json::<Vec<SerialKeyIndexEntry>>()
+                        .context("Error reading key index response")?;
+                for key in index {
+                    let mut window = match &key.meta {
+                        Some(meta) => format!(
+                            "{} to {}",
+                            meta.activated.format("%Y-%m-%d"),
+                            meta.retired.map(|r| r.format("%Y-%m-%d").to_string()).unwrap_or_else(|| "now".to_string())
+                        ),
+                        None => "no lifecycle published".to_string(),
+                    };
+                    if let Some(compromised) = key.compromised {
+                        window.push_str(&format!(", compromised {}", compromised.to_rfc3339()));
+                    }
+                    println!(
+                        "- `{}` ({}, {}): {}",
+                        key.fingerprint,
+                        key.algorithm,
+                        format!("{:?}", key.status).to_lowercase(),
+                        window
+                    );
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    match inner() {
+        Ok(_) => { },
+        Err(e) => {
+            fatal(e);
+        },
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    sync::Arc,
+    time::Duration,
+};
+use loga::{
+    ea,
+    Log,
+    ResultContext,
+};
+use shared::{
+    api::ApiErrorCode,
+    hash::HashAlgorithm,
+    batch_leaf,
+    canonical,
+    merkle,
+    transparency::{
+        LogEntry,
+        StampFormat,
+    },
+    BatchReceipt,
+    SerialBatch,
+    SerialStamp,
+    STAMP_VERSION,
+};
+use tokio::{
+    sync::{
+        mpsc,
+        oneshot,
+        Mutex,
+    },
+    time::{
+        timeout_at,
+        Instant,
+    },
+};
+use crate::{
+    error::{
+        ApiError,
+        ResultApi,
+    },
+    signer::SignerHandle,
+    stamp::Issuer,
+    transparency::TransparencyLog,
+};
+
+struct BatchRequest {
+    algorithm: HashAlgorithm,
+    hash: String,
+    blinded: bool,
+    nonce: Option<String>,
+    idempotent: bool,
+    reply: oneshot::Sender<Result<LogEntry, ApiError>>,
+}
+
+/// Collects hashes for a window and stamps them all with one signature over
+/// their merkle root.  The batch is logged before it joins the chain, like a
+/// direct stamp.
+#[derive(Clone)]
+pub struct Batcher {
+    queue: mpsc::Sender<BatchRequest>,
+}
+
+impl Batcher {
+    pub fn spawn(
+        log: Log,
+        signer: SignerHandle,
+        issuer: Arc<Issuer>,
+        translog: Arc<Mutex<TransparencyLog>>,
+        window: Duration,
+        max_size: usize,
+    ) -> Batcher {
+        let (queue, mut queue_recv) = mpsc::channel::<BatchRequest>(max_size);
+        tokio::spawn(async move {
+            while let Some(first) = queue_recv.recv().await {
+                let deadline = Instant::now() + window;
+                let mut batch = vec![first];
+                while batch.len() < max_size {
+                    match timeout_at(deadline, queue_recv.recv()).await {
+                        Ok(Some(req)) => batch.push(req),
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+                // Idempotent requests get the earliest logged stamp for the hash, or
+                // share the stamp of an earlier request for it in this batch.  Only
+                // this task logs notary stamps when batching, so nothing can slip in
+                // between.
+                let mut stamping = vec![];
+                let mut followers = vec![];
+                {
+                    let translog = translog.lock().await;
+                    for req in batch {
+                        if req.idempotent {
+                            if let Some(entry) = translog.earliest_stamp(req.algorithm, &req.hash) {
+                                _ = req.reply.send(Ok(entry.clone()));
+                                continue;
+                            }
+                            if let Some(i) =
+                                stamping
+                                    .iter()
+                                    .position(|r: &BatchRequest| r.algorithm == req.algorithm && r.hash == req.hash) {
+                                followers.push((i, req));
+                                continue;
+                            }
+                        }
+                        stamping.push(req);
+                    }
+                }
+                if stamping.is_empty() {
+                    continue;
+                }
+                let size = stamping.len();
+                let result = async {
+                    let leaves =
+                        stamping
+                            .iter()
+                            .map(|req| batch_leaf(req.algorithm, &req.hash, req.blinded, req.nonce.as_deref()))
+                            .collect::<Vec<_>>();
+                    let mut signed = SerialBatch {
+                        version: STAMP_VERSION,
+                        root: hex::encode(merkle::root(&leaves)),
+                        size: leaves.len() as u64,
+                        stamp: issuer.clock.stamp_time()?,
+                        serial: None,
+                        notary: issuer.notary.clone(),
+                        key: Some(issuer.key.clone()),
+                        previous: None,
+                        roughtime: vec![],
+                    };
+
+                    // Attest before taking the chain lock, then settle the time and chain
+                    // link under it (see `Stamper::stamp`)
+                    let attested = match &issuer.attester {
+                        Some(attester) => Some(attester.attest(&canonical::to_vec(&signed).unwrap()).await?),
+                        None => None,
+                    };
+                    let mut chain = issuer.chain().await;
+                    signed.stamp = issuer.clock.stamp_time()?;
+                    signed.serial = Some(chain.next_serial());
+                    signed.previous = chain.previous.clone();
+                    if let Some(attested) = attested {
+                        signed.roughtime = attested.agreeing(signed.stamp)?;
+                    }
+                    let body = canonical::to_vec(&signed).unwrap();
+                    let message =
+                        String::from_utf8(signer.sign_message(body.clone()).await?)
+                            .context("Failed to convert armor into string")
+                            .api_code(ApiErrorCode::Internal)?;
+                    let mut entries = vec![];
+                    for (i, req) in stamping.iter().enumerate() {
+                        let path = merkle::inclusion_proof(i, &leaves).iter().map(hex::encode).collect();
+                        let stamp = SerialStamp {
+                            version: signed.version,
+                            algorithm: req.algorithm,
+                            hash: req.hash.clone(),
+                            blinded: req.blinded,
+                            stamp: signed.stamp,
+                            serial: signed.serial,
+                            notary: signed.notary.clone(),
+                            key: signed.key.clone(),
+                            nonce: req.nonce.clone(),
+                            previous: signed.previous.clone(),
+                            roughtime: signed.roughtime.clone(),
+                        };
+                        let receipt = BatchReceipt {
+                            algorithm: req.algorithm,
+                            hash: req.hash.clone(),
+                            blinded: req.blinded,
+                            nonce: req.nonce.clone(),
+                            index: i as u64,
+                            path: path,
+                            message: message.clone(),
+                        };
+                        entries.push(LogEntry {
+                            stamp: stamp,
+                            stamp_file: serde_json::to_string(&receipt).unwrap(),
+                            format: StampFormat::NotaryStamp,
+                        });
+                    }
+
+                    // Don't hand out stamps unless they've been logged, and don't link
+                    // to a signature that wasn't
+                    translog
+                        .lock()
+                        .await
+                        .append_all(entries.clone())
+                        .context("Failed to record batch in transparency log")
+                        .api_code(ApiErrorCode::LogFailed)?;
+                    chain.extend(signed.serial.unwrap(), &body);
+                    return Ok::<_, ApiError>(entries);
+                }.await;
+                match result {
+                    Ok(entries) => {
+                        for (i, req) in followers {
+                            _ = req.reply.send(Ok(entries[i].clone()));
+                        }
+                        for (req, entry) in stamping.into_iter().zip(entries) {
+                            _ = req.reply.send(Ok(entry));
+                        }
+                    },
+                    Err(e) => {
+                        let code = e.code;
+                        log.warn_e(e.err, "Error stamping batch", ea!(size = size.to_string()));
+                        for req in stamping.into_iter().chain(followers.into_iter().map(|(_, req)| req)) {
+                            _ = req.reply.send(Err(ApiError::new(code, loga::err("Error stamping batch"))));
+                        }
+                    },
+                }
+            }
+        });
+        return Batcher { queue: queue };
+    }
+
+    /// Returns the logged entry, whose stamp file is the receipt.  If
+    /// `idempotent` it may be an earlier stamp for the hash.
+    pub async fn stamp(
+        &self,
+        algorithm: HashAlgorithm,
+        hash: String,
+        blinded: bool,
+        nonce: Option<String>,
+        idempotent: bool,
+    ) -> Result<LogEntry, ApiError> {
+        let (reply, reply_recv) = oneshot::channel();
+        self.queue.send(BatchRequest {
+            algorithm: algorithm,
+            hash: hash,
+            blinded: blinded,
+            nonce: nonce,
+            idempotent: idempotent,
+            reply: reply,
+        }).await.map_err(|_| ApiError::new(ApiErrorCode::Internal, loga::err("Batcher exited")))?;
+        return reply_recv.await.context("Batcher dropped request").api_code(ApiErrorCode::Internal)?;
+    }
+}
@@ -0,0 +1,361 @@
+//! Stamp times come from the host clock, which can jump backwards (NTP
+//! misconfiguration, a restored VM) or drift.  Stamps are refused rather than
+//! issued with a time that contradicts earlier stamps or the reference time
+//! source.
+use std::{
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::Duration,
+};
+use chrono::{
+    DateTime,
+    TimeZone,
+    Utc,
+};
+use loga::{
+    ea,
+    Log,
+    ResultContext,
+};
+use shared::{
+    api::{
+        ApiErrorCode,
+        SerialClockStatus,
+    },
+    roughtime::NONCE_LEN,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{
+        sleep,
+        timeout,
+    },
+};
+use crate::{
+    error::ApiError,
+    roughtime::RoughtimeServer,
+};
+
+pub enum TimeSource {
+    /// SNTP query to `host:port`
+    Ntp(String),
+    Roughtime(RoughtimeServer),
+}
+
+pub struct Reference {
+    source: TimeSource,
+    /// Refuse to stamp if the clock is further than this from the reference
+    tolerance: chrono::Duration,
+    /// How often to compare with the reference
+    interval: Duration,
+    /// Stamps are refused if the last comparison is older than this, 3 intervals
+    stale: chrono::Duration,
+}
+
+impl Reference {
+    /// Errors if the durations are too large to compare times with.
+    pub fn new(source: TimeSource, tolerance: Duration, interval: Duration) -> Result<Reference, loga::Error> {
+        let tolerance =
+            chrono::Duration::from_std(
+                tolerance,
+            ).map_err(|_| loga::err_with("Clock tolerance is too large", ea!(ms = tolerance.as_millis().to_string())))?;
+        let Some(stale) = interval.checked_mul(3).and_then(|d| chrono::Duration::from_std(d).ok()) else {
+            return Err(
+                loga::err_with("Clock check interval is too large", ea!(secs = interval.as_secs().to_string())),
+            );
+        };
+        return Ok(Reference {
+            source: source,
+            tolerance: tolerance,
+            interval: interval,
+            stale: stale,
+        });
+    }
+}
+
+struct Check {
+    at: DateTime<Utc>,
+    /// How far ahead of the reference the clock is, or why the reference couldn't
+    /// be read
+    offset: Result<chrono::Duration, String>,
+}
+
+pub struct Clock {
+    /// Time of the latest stamp issued, starting from the transparency log
+    last: Mutex<Option<DateTime<Utc>>>,
+    reference: Option<(Reference, Mutex<Option<Check>>)>,
+}
+
+impl Clock {
+    /// Starts comparing with `reference` in the background, if there is one.
+    pub fn spawn(log: Log, last: Option<DateTime<Utc>>, reference: Option<Reference>) -> Arc<Clock> {
+        let clock = Arc::new(Clock {
+            last: Mutex::new(last),
+            reference: reference.map(|r| (r, Mutex::new(None))),
+        });
+        if clock.reference.is_some() {
+            tokio::spawn({
+                let clock = clock.clone();
+                async move {
+                    let (reference, check) = clock.reference.as_ref().unwrap();
+                    loop {
+                        let offset = match reference_offset(&reference.source).await {
+                            Ok(o) => Ok(o),
+                            Err(e) => {
+                                let message = e.to_string();
+                                log.warn_e(e, "Error reading reference time", ea!());
+                                Err(message)
+                            },
+                        };
+                        *check.lock().unwrap() = Some(Check {
+                            at: Utc::now(),
+                            offset: offset,
+                        });
+                        sleep(reference.interval).await;
+                    }
+                }
+            });
+        }
+        return clock;
+    }
+
+    /// Why a stamp made at `now` would be refused
+    fn problem(&self, now: DateTime<Utc>, last: Option<DateTime<Utc>>) -> Option<String> {
+        if let Some(last) = last {
+            if now < last {
+                return Some(format!("Clock is behind the last stamp ({})", last.to_rfc3339()));
+            }
+        }
+        if let Some((reference, check)) = &self.reference {
+            let check = check.lock().unwrap();
+            let Some(check) = check.as_ref() else {
+                return Some("Clock hasn't been compared with the reference yet".to_string());
+            };
+            if now - check.at > reference.stale {
+                return Some(format!("Clock was last compared with the reference at {}", check.at.to_rfc3339()));
+            }
+            match &check.offset {
+                Ok(offset) => {
+                    if offset.abs() > reference.tolerance {
+                        return Some(
+                            format!("Clock is {}ms off from the reference", offset.num_milliseconds()),
+                        );
+                    }
+                },
+                Err(e) => return Some(format!("Couldn't read the reference time: {}", e)),
+            }
+        }
+        return None;
+    }
+
+    /// The time for a new stamp, which becomes the time later stamps can't be
+    /// before.
+    pub fn stamp_time(&self) -> Result<DateTime<Utc>, ApiError> {
+        return self.stamp_time_at(Utc::now());
+    }
+
+    fn stamp_time_at(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>, ApiError> {
+        let mut last = self.last.lock().unwrap();
+        if let Some(problem) = self.problem(now, *last) {
+            return Err(
+                ApiError::new(
+                    ApiErrorCode::ClockUnreliable,
+                    loga::err_with("Refusing to stamp", ea!(problem = problem)),
+                ),
+            );
+        }
+        *last = Some(now);
+        return Ok(now);
+    }
+
+    pub fn status(&self) -> SerialClockStatus {
+        let last = *self.last.lock().unwrap();
+        let now = Utc::now();
+        let problem = self.problem(now, last);
+        let (checked, offset) = match &self.reference {
+            Some((_, check)) => match check.lock().unwrap().as_ref() {
+                Some(check) => (Some(check.at), check.offset.as_ref().ok().map(|o| o.num_milliseconds())),
+                None => (None, None),
+            },
+            None => (None, None),
+        };
+        return SerialClockStatus {
+            ok: problem.is_none(),
+            now: now,
+            last_stamp: last,
+            reference_checked: checked,
+            reference_offset_ms: offset,
+            problem: problem,
+        };
+    }
+}
+
+/// How far the clock is ahead of `source`.
+async fn reference_offset(source: &TimeSource) -> Result<chrono::Duration, loga::Error> {
+    match source {
+        TimeSource::Ntp(server) => {
+            let (sent, reference, received) = query_ntp(server).await?;
+
+            // Assume the reply took as long as the request
+            let reference = reference + (received - sent) / 2;
+            return Ok(received - reference);
+        },
+        TimeSource::Roughtime(server) => {
+            let mut nonce = [0u8; NONCE_LEN];
+            sequoia_openpgp::crypto::random(&mut nonce);
+            let sent = Utc::now();
+            let (_, attestation) = server.query(&nonce).await?;
+            let received = Utc::now();
+            return Ok(sent + (received - sent) / 2 - attestation.midpoint);
+        },
+    }
+}
+
+/// Seconds from the NTP epoch (1900) to the unix epoch
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+/// Returns the local time the request was sent, the server's transmit time, and
+/// the local time the reply arrived.
+async fn query_ntp(server: &str) -> Result<(DateTime<Utc>, DateTime<Utc>, DateTime<Utc>), loga::Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.context("Error opening NTP socket")?;
+    socket.connect(server).await.context_with("Error resolving NTP server", ea!(server = server.to_string()))?;
+
+    // Leap indicator 0, version 4, mode 3 (client)
+    let mut request = [0u8; 48];
+    request[0] = 0b00_100_011;
+    let sent = Utc::now();
+    socket.send(&request).await.context_with("Error sending NTP request", ea!(server = server.to_string()))?;
+    let mut reply = [0u8; 48];
+    let size =
+        timeout(Duration::from_secs(5), socket.recv(&mut reply))
+            .await
+            .map_err(|_| loga::err_with("Timed out waiting for NTP reply", ea!(server = server.to_string())))?
+            .context_with("Error receiving NTP reply", ea!(server = server.to_string()))?;
+    let received = Utc::now();
+    if size < 48 {
+        return Err(loga::err_with("NTP reply too short", ea!(server = server.to_string())));
+    }
+    let seconds = u32::from_be_bytes(reply[40 .. 44].try_into().unwrap()) as i64;
+    let fraction = u32::from_be_bytes(reply[44 .. 48].try_into().unwrap()) as u64;
+    if seconds == 0 {
+        return Err(loga::err_with("NTP server isn't synchronized", ea!(server = server.to_string())));
+    }
+    let transmit =
+        Utc
+            .timestamp_opt(seconds - NTP_UNIX_OFFSET, ((fraction * 1_000_000_000) >> 32) as u32)
+            .single()
+            .ok_or_else(|| loga::err_with("NTP reply has an invalid time", ea!(server = server.to_string())))?;
+    return Ok((sent, transmit, received));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Mutex,
+        time::Duration,
+    };
+    use chrono::{
+        DateTime,
+        TimeZone,
+        Utc,
+    };
+    use shared::api::ApiErrorCode;
+    use super::{
+        Check,
+        Clock,
+        Reference,
+        TimeSource,
+    };
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        return Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap();
+    }
+
+    /// A clock checked against a reference (tolerance 1s, interval 60s) at
+    /// `checked` with the given result
+    fn referenced(checked: Option<(DateTime<Utc>, Result<chrono::Duration, String>)>) -> Clock {
+        let reference =
+            Reference::new(
+                TimeSource::Ntp("127.0.0.1:123".to_string()),
+                Duration::from_secs(1),
+                Duration::from_secs(60),
+            ).unwrap();
+        return Clock {
+            last: Mutex::new(None),
+            reference: Some((reference, Mutex::new(checked.map(|(at, offset)| Check {
+                at: at,
+                offset: offset,
+            })))),
+        };
+    }
+
+    fn refused(clock: &Clock, now: DateTime<Utc>) -> bool {
+        match clock.stamp_time_at(now) {
+            Ok(_) => return false,
+            Err(e) => {
+                assert_eq!(e.code, ApiErrorCode::ClockUnreliable);
+                return true;
+            },
+        }
+    }
+
+    #[test]
+    fn backwards() {
+        let clock = Clock {
+            last: Mutex::new(Some(at(10))),
+            reference: None,
+        };
+        assert!(refused(&clock, at(9)));
+        assert!(clock.problem(at(9), Some(at(10))).is_some());
+        assert_eq!(clock.stamp_time_at(at(10)).ok(), Some(at(10)));
+        assert_eq!(clock.stamp_time_at(at(12)).ok(), Some(at(12)));
+        assert!(refused(&clock, at(11)));
+        assert_eq!(*clock.last.lock().unwrap(), Some(at(12)));
+    }
+
+    #[test]
+    fn unchecked() {
+        assert!(refused(&referenced(None), at(0)));
+        assert!(refused(&referenced(Some((at(0), Err("timed out".to_string())))), at(1)));
+    }
+
+    #[test]
+    fn offset() {
+        assert!(!refused(&referenced(Some((at(0), Ok(chrono::Duration::milliseconds(1000))))), at(1)));
+        assert!(!refused(&referenced(Some((at(0), Ok(chrono::Duration::milliseconds(-1000))))), at(1)));
+        assert!(refused(&referenced(Some((at(0), Ok(chrono::Duration::milliseconds(1001))))), at(1)));
+        assert!(refused(&referenced(Some((at(0), Ok(chrono::Duration::milliseconds(-1001))))), at(1)));
+    }
+
+    #[test]
+    fn stale() {
+        let clock = referenced(Some((at(0), Ok(chrono::Duration::zero()))));
+        assert!(clock.problem(at(180), None).is_none());
+        assert!(clock.problem(at(181), None).is_some());
+        assert!(!refused(&clock, at(180)));
+        assert!(refused(&clock, at(181)));
+    }
+
+    #[test]
+    fn status() {
+        let clock = referenced(Some((at(0), Ok(chrono::Duration::milliseconds(5)))));
+        let status = clock.status();
+        assert_eq!(status.reference_checked, Some(at(0)));
+        assert_eq!(status.reference_offset_ms, Some(5));
+
+        // The check was long before the real current time
+        assert!(!status.ok);
+        assert!(status.problem.is_some());
+    }
+
+    #[test]
+    fn invalid_config() {
+        let ntp = || TimeSource::Ntp("127.0.0.1:123".to_string());
+        assert!(Reference::new(ntp(), Duration::from_secs(1), Duration::MAX).is_err());
+        assert!(Reference::new(ntp(), Duration::from_secs(1), Duration::from_secs(u64::MAX / 3)).is_err());
+        assert!(Reference::new(ntp(), Duration::MAX, Duration::from_secs(60)).is_err());
+        assert!(Reference::new(ntp(), Duration::from_secs(1), Duration::from_secs(60)).is_ok());
+    }
+}
@@ -0,0 +1,69 @@
+//! Errors reported to api clients as a `SerialApiError`.
+use loga::{
+    ea,
+    Log,
+};
+use poem::{
+    http::StatusCode,
+    web::Json,
+    IntoResponse,
+    Response,
+};
+use shared::api::{
+    ApiErrorCode,
+    SerialApiError,
+};
+
+/// An error with the code to report to the client.  The details stay in the
+/// server log.
+pub struct ApiError {
+    pub code: ApiErrorCode,
+    pub err: loga::Error,
+}
+
+impl ApiError {
+    pub fn new(code: ApiErrorCode, err: loga::Error) -> ApiError {
+        return ApiError {
+            code: code,
+            err: err,
+        };
+    }
+
+    /// Log server-side errors and respond with the code.
+    pub fn response(self, log: &Log, message: &'static str) -> Response {
+        let code = self.code;
+        if !code.is_client_error() {
+            log.warn_e(self.err, message, ea!(code = code.to_string()));
+        }
+        return error_response(code, code.to_string());
+    }
+}
+
+pub fn error_response(code: ApiErrorCode, message: String) -> Response {
+    let status = match code {
+        ApiErrorCode::BadHash | ApiErrorCode::BadAlgorithm | ApiErrorCode::BadNonce => StatusCode::BAD_REQUEST,
+        ApiErrorCode::NotStamped => StatusCode::NOT_FOUND,
+        ApiErrorCode::KeyInactive |
+        ApiErrorCode::CardUnavailable |
+        ApiErrorCode::PinLocked |
+        ApiErrorCode::ClockUnreliable |
+        ApiErrorCode::RoughtimeUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ApiErrorCode::SigningFailed | ApiErrorCode::LogFailed | ApiErrorCode::Internal => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+    };
+    return (status, Json(SerialApiError {
+        code: code,
+        message: message,
+    })).into_response();
+}
+
+pub trait ResultApi<T> {
+    fn api_code(self, code: ApiErrorCode) -> Result<T, ApiError>;
+}
+
+impl<T> ResultApi<T> for Result<T, loga::Error> {
+    fn api_code(self, code: ApiErrorCode) -> Result<T, ApiError> {
+        return self.map_err(|e| ApiError::new(code, e));
+    }
+}
@@ -0,0 +1,124 @@
+//! The machine-readable index of published seal keys (`/api/keys`), so clients
+//! don't need to know key ids up front.
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+use chrono::Utc;
+use loga::{
+    ea,
+    ResultContext,
+};
+use sequoia_openpgp::{
+    parse::Parse,
+    serialize::SerializeInto,
+    Cert,
+};
+use shared::keys::{
+    compromise_filename,
+    meta_filename,
+    parse_compromise,
+    KeyStatus,
+    SerialKeyIndexEntry,
+    SerialKeyMeta,
+};
+
+/// Read a file in `keys_dir` that may not exist.
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, loga::Error> {
+    match fs::read(path) {
+        Ok(data) => return Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context_with("Error reading file", ea!(path = path.to_string_lossy().to_string())),
+    }
+}
+
+/// Published certs are the files in `keys_dir` named by a bare key id, the
+/// metadata and compromise declarations next to them have extensions. Keys are
+/// listed oldest first.
+pub fn key_index(keys_dir: &Path) -> Result<Vec<SerialKeyIndexEntry>, loga::Error> {
+    let now = Utc::now();
+    let mut out = vec![];
+    for entry in fs::read_dir(
+        keys_dir,
+    ).context_with("Error listing keys dir", ea!(path = keys_dir.to_string_lossy().to_string()))? {
+        let path = entry.context("Error reading keys dir entry")?.path();
+        let Some(key_id) = path.file_name().and_then(|n| n.to_str()).map(|n| n.to_string()) else {
+            continue;
+        };
+        if key_id.contains('.') || !path.is_file() {
+            continue;
+        }
+        let cert =
+            Cert::from_file(
+                &path,
+            ).map_err(
+                |e| loga::err_with(
+                    "Error reading published cert",
+                    ea!(path = path.to_string_lossy().to_string(), err = e.to_string()),
+                ),
+            )?;
+        let Some(key) = cert.keys().find(|k| k.keyid().to_hex().to_lowercase() == key_id) else {
+            return Err(
+                loga::err_with(
+                    "Published cert doesn't contain the key it's named after",
+                    ea!(path = path.to_string_lossy().to_string()),
+                ),
+            );
+        };
+        let meta_path = keys_dir.join(meta_filename(&key_id));
+        let mut meta = match read_optional(&meta_path)? {
+            Some(data) => Some(
+                serde_json::from_slice::<SerialKeyMeta>(
+                    &data,
+                ).context_with("Error parsing seal key metadata", ea!(path = meta_path.to_string_lossy().to_string()))?,
+            ),
+            None => None,
+        };
+        let compromise_path = keys_dir.join(compromise_filename(&key_id));
+        let compromised = match read_optional(&compromise_path)? {
+            Some(data) => Some(
+                parse_compromise(
+                    &data,
+                    &cert,
+                )
+                    .context_with(
+                        "Invalid compromise declaration",
+                        ea!(path = compromise_path.to_string_lossy().to_string()),
+                    )?
+                    .compromised,
+            ),
+            None => None,
+        };
+        if let (Some(meta), Some(compromised)) = (&mut meta, compromised) {
+            meta.compromised = Some(meta.compromised.map_or(compromised, |t| t.min(compromised)));
+        }
+        let compromised = meta.as_ref().and_then(|m| m.compromised).or(compromised);
+        let status = match (&meta, compromised) {
+            (_, Some(_)) => KeyStatus::Compromised,
+            (Some(meta), None) => meta.status(now),
+            (None, None) => KeyStatus::Unknown,
+        };
+        let armored =
+            cert
+                .armored()
+                .to_vec()
+                .map_err(
+                    |e| loga::err_with(
+                        "Error armoring published cert",
+                        ea!(path = path.to_string_lossy().to_string(), err = e.to_string()),
+                    ),
+                )?;
+        out.push(SerialKeyIndexEntry {
+            fingerprint: key.fingerprint().to_hex(),
+            algorithm: key.pk_algo().to_string(),
+            key_id: key_id,
+            meta: meta,
+            compromised: compromised,
+            status: status,
+            cert: String::from_utf8(armored).unwrap(),
+        });
+    }
+    out.sort_by_key(|e| (e.meta.as_ref().map(|m| m.activated), e.key_id.clone()));
+    return Ok(out);
+}
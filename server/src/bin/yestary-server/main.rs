@@ -0,0 +1,740 @@
+use std::{
+    env,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use aargvark::vark;
+use loga::{
+    ea,
+    Log,
+    fatal,
+    ResultContext,
+};
+use poem::{
+    Server,
+    listener::TcpListener,
+    Route,
+    endpoint::{
+        StaticFilesEndpoint,
+    },
+    web::{
+        Data,
+        Json,
+        Path,
+        Query,
+    },
+    Response,
+    http::StatusCode,
+    middleware::AddData,
+    get,
+    handler,
+    post,
+    Request,
+    IntoResponse,
+    EndpointExt,
+};
+use sequoia_openpgp::Fingerprint;
+use serde::Deserialize;
+use shared::{
+    api::{
+        ApiErrorCode,
+        SerialHealth,
+    },
+    hash::HashAlgorithm,
+    merkle,
+    transparency::{
+        ConsistencyProof,
+        InclusionProof,
+        SerialEarliest,
+    },
+};
+use tokio::{
+    select,
+    sync::Mutex,
+};
+use crate::{
+    batch::Batcher,
+    clock::{
+        Clock,
+        Reference,
+        TimeSource,
+    },
+    error::{
+        error_response,
+        ApiError,
+    },
+    roughtime::{
+        Attester,
+        RoughtimeServer,
+    },
+    signer::{
+        CardStampSigner,
+        SignerHandle,
+        SoftwareStampSigner,
+        StampSigner,
+    },
+    stamp::{
+        Issuer,
+        Stamper,
+    },
+    transparency::TransparencyLog,
+    tsp::Tsa,
+};
+
+mod batch;
+mod clock;
+mod error;
+mod keys;
+mod roughtime;
+mod signer;
+mod stamp;
+mod transparency;
+mod tsp;
+
+mod args {
+    use std::{
+        net::{
+            SocketAddr,
+        },
+        path::PathBuf,
+    };
+    use aargvark::{
+        Aargvark,
+        AargvarkJson,
+    };
+    use serde::{
+        Serialize,
+        Deserialize,
+    };
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum PinSource {
+        /// Read the pin from a file, surrounding whitespace is ignored
+        File(PathBuf),
+        /// Read the pin from the named environment variable
+        Env(String),
+        /// Read the pin from the named systemd credential (`LoadCredential=` etc.)
+        SystemdCredential(String),
+        /// Ask for the pin on the terminal at startup
+        Prompt,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum SignerConfig {
+        /// Sign with an OpenPGP card via pcsc
+        Card {
+            /// The card's ident (`MANUFACTURER:SERIAL`, as shown by `opgpcard list`)
+            ident: String,
+            /// Hex fingerprint of the signing key on the card. The matching cert must
+            /// be published in `keys_dir`.
+            fingerprint: String,
+            /// Verified when the card is connected.  If the card rejects it, signing
+            /// stops until restart rather than using up the card's pin tries.
+            pin: PinSource,
+        },
+        /// Sign with an unencrypted secret key on disk, for development and testing
+        Software {
+            tsk_path: PathBuf,
+        },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct BatchConfig {
+        /// How long to collect hashes before signing them together, in milliseconds
+        pub window_ms: u64,
+        /// Sign early once this many hashes are waiting. Defaults to 4096.
+        pub max_size: Option<usize>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct TspConfig {
+        /// X.509 certificate (PEM or DER) for the signing key, with the
+        /// `timeStamping` extended key usage. Only RSA and ECDSA keys are supported.
+        pub cert_path: PathBuf,
+        /// OID of the policy tokens are issued under
+        pub policy: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct RoughtimeServerConfig {
+        /// `host:port`
+        pub addr: String,
+        /// Hex ed25519 long-term public key of the server
+        pub public_key: String,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TimeReference {
+        /// An NTP server as `host:port`, like `pool.ntp.org:123`
+        Ntp(String),
+        Roughtime(RoughtimeServerConfig),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ClockConfig {
+        pub reference: TimeReference,
+        /// Refuse to stamp if the clock is further than this from the reference, in
+        /// milliseconds. Defaults to 1000.
+        pub tolerance_ms: Option<u64>,
+        /// How often to compare the clock with the reference, in seconds. Defaults
+        /// to 60.
+        pub check_interval_secs: Option<u64>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct RoughtimeConfig {
+        pub servers: Vec<RoughtimeServerConfig>,
+        /// How many servers must attest each stamp. Defaults to all of them.
+        pub required: Option<usize>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Config {
+        pub web_bind_addr: SocketAddr,
+        /// Published seal certs, named by lowercase hex key id, each with lifecycle
+        /// metadata in `<key id>.meta.json` (see `shared::keys`). Served at
+        /// `/api/key/`.
+        pub keys_dir: PathBuf,
+        pub static_dir: PathBuf,
+        pub signer: SignerConfig,
+        /// How many stamp requests can wait for the signer before new requests block.
+        /// Defaults to 64.
+        pub signer_queue_size: Option<usize>,
+        /// Stamp hashes in batches, signing only the merkle root of each batch.
+        /// Stamps are returned as `BatchReceipt` json instead of a signed message.
+        pub batch: Option<BatchConfig>,
+        /// Where to keep the append-only log of every issued stamp
+        pub transparency_log_path: PathBuf,
+        /// Serve RFC 3161 time-stamp requests at `/api/tsp`
+        pub tsp: Option<TspConfig>,
+        /// Identifies this notary instance in the stamps it issues, like its public
+        /// url. Left out of stamps if not set.
+        pub notary_id: Option<String>,
+        /// Refuse to stamp when the clock disagrees with a reference time source.
+        /// Stamps are always refused if the clock is behind the last stamp issued.
+        pub clock: Option<ClockConfig>,
+        /// Have Roughtime servers attest the time of every stamp before it's signed,
+        /// embedding their responses in the stamp. RFC 3161 tokens aren't attested.
+        pub roughtime: Option<RoughtimeConfig>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Args {
+        pub config: AargvarkJson<Config>,
+    }
+}
+
+fn read_pin(source: &args::PinSource) -> Result<String, loga::Error> {
+    let pin = match source {
+        args::PinSource::File(path) => {
+            fs::read_to_string(
+                path,
+            ).context_with("Error reading card pin file", ea!(path = path.to_string_lossy().to_string()))?
+        },
+        args::PinSource::Env(name) => {
+            env::var(name).context_with("Error reading card pin from environment", ea!(var = name.clone()))?
+        },
+        args::PinSource::SystemdCredential(name) => {
+            let dir =
+                env::var(
+                    "CREDENTIALS_DIRECTORY",
+                ).context("No systemd credentials directory in environment, is LoadCredential set?")?;
+            fs::read_to_string(
+                PathBuf::from(dir).join(name),
+            ).context_with("Error reading card pin systemd credential", ea!(credential = name.clone()))?
+        },
+        args::PinSource::Prompt => {
+            rpassword::prompt_password("Card PIN: ").context("Error reading card pin from terminal")?
+        },
+    };
+    let pin = pin.trim().to_string();
+    if pin.is_empty() {
+        return Err(loga::err("Card pin is empty"));
+    }
+    return Ok(pin);
+}
+
+fn roughtime_server(config: &args::RoughtimeServerConfig) -> Result<RoughtimeServer, loga::Error> {
+    let public_key =
+        shared::roughtime::parse_public_key(
+            &config.public_key,
+        ).ok_or_else(
+            || loga::err_with("Roughtime server public key must be 64 hex digits", ea!(server = config.addr.clone())),
+        )?;
+    return Ok(RoughtimeServer {
+        addr: config.addr.clone(),
+        public_key: public_key,
+    });
+}
+
+#[derive(Deserialize)]
+struct StampQuery {
+    /// The algorithm that made the hash being stamped, default sha256.  Parsed
+    /// in the handler so a bad one gets a json error.
+    algorithm: Option<String>,
+    /// Included in the signed stamp, up to 128 printable ascii characters
+    nonce: Option<String>,
+    /// The hash is a commitment to the document hash, see `shared::blind`
+    blinded: Option<bool>,
+    /// If the hash was stamped before, return the earliest stamp instead of a
+    /// new one
+    idempotent: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct StampsQuery {
+    /// As in `StampQuery`
+    algorithm: Option<String>,
+}
+
+/// A stamp file as issued, batch receipts being json.
+fn stamp_response(stamp_file: String) -> Response {
+    let resp = Response::builder();
+    let resp = match stamp_file.trim_start().starts_with('{') {
+        true => resp.content_type("application/json"),
+        false => resp,
+    };
+    return resp.body(stamp_file);
+}
+
+/// The algorithm named in a query (default sha256) if `hash` is valid for it,
+/// otherwise the error code and message for the json error response.
+fn parse_hash(algorithm: Option<&str>, hash: &str) -> Result<HashAlgorithm, (ApiErrorCode, String)> {
+    let algorithm = match algorithm {
+        Some(a) => match HashAlgorithm::from_str(a) {
+            Ok(a) => a,
+            Err(e) => return Err((ApiErrorCode::BadAlgorithm, e)),
+        },
+        None => HashAlgorithm::default(),
+    };
+    if !algorithm.is_valid_hex(hash) {
+        return Err(
+            (
+                ApiErrorCode::BadHash,
+                format!("A {} hash is {} lowercase hex digits", algorithm, algorithm.digest_len() * 2),
+            ),
+        );
+    }
+    return Ok(algorithm);
+}
+
+#[derive(Deserialize)]
+struct InclusionQuery {
+    /// Hex leaf hash of the stamp, see `shared::transparency::stamp_leaf_hash`
+    leaf_hash: String,
+    tree_size: u64,
+}
+
+#[derive(Deserialize)]
+struct ConsistencyQuery {
+    first: u64,
+    second: u64,
+}
+
+#[derive(Deserialize)]
+struct EntriesQuery {
+    start: u64,
+    /// Exclusive. At most 1000 entries are returned at once.
+    end: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    async fn inner() -> Result<(), loga::Error> {
+        let config = vark::<args::Args>().config.value;
+        let log = &loga::new(loga::Level::Info);
+        let tm = taskmanager::TaskManager::new();
+        let signer: Box<dyn StampSigner> = match &config.signer {
+            args::SignerConfig::Card { ident, fingerprint, pin } => Box::new(
+                CardStampSigner::new(
+                    ident.clone(),
+                    Fingerprint::from_hex(
+                        fingerprint,
+                    ).map_err(
+                        |e| loga::err_with(
+                            "Invalid card signing key fingerprint in config",
+                            ea!(fingerprint = fingerprint.clone(), err = e.to_string()),
+                        ),
+                    )?,
+                    read_pin(pin)?,
+                    &config.keys_dir,
+                )?,
+            ),
+            args::SignerConfig::Software { tsk_path } => Box::new(SoftwareStampSigner::new(tsk_path)?),
+        };
+        let translog = TransparencyLog::open(&config.transparency_log_path)?;
+        let signer = SignerHandle::spawn(signer, &config.keys_dir, config.signer_queue_size.unwrap_or(64))?;
+        let tsa = match &config.tsp {
+            Some(tsp) => Some(Tsa::load(&tsp.cert_path, &tsp.policy, &signer.public_key)?),
+            None => None,
+        };
+        let reference = match &config.clock {
+            Some(c) => Some(
+                Reference::new(
+                    match &c.reference {
+                        args::TimeReference::Ntp(server) => TimeSource::Ntp(server.clone()),
+                        args::TimeReference::Roughtime(server) => TimeSource::Roughtime(roughtime_server(server)?),
+                    },
+                    Duration::from_millis(c.tolerance_ms.unwrap_or(1000)),
+                    Duration::from_secs(c.check_interval_secs.unwrap_or(60)),
+                ).context("Invalid clock config")?,
+            ),
+            None => None,
+        };
+        let clock = Clock::spawn(log.fork(ea!(sys = "clock")), translog.last_stamp_time(), reference);
+        let attester = match &config.roughtime {
+            Some(c) => Some(
+                Attester::new(
+                    c.servers.iter().map(roughtime_server).collect::<Result<Vec<_>, _>>()?,
+                    c.required,
+                ).context("Invalid Roughtime config")?,
+            ),
+            None => None,
+        };
+        let issuer =
+            Arc::new(
+                Issuer::new(
+                    config.notary_id.clone(),
+                    signer.public_key.fingerprint().to_hex(),
+                    clock,
+                    attester,
+                    &translog,
+                )?,
+            );
+        let translog = Arc::new(Mutex::new(translog));
+        let batcher = config.batch.as_ref().map(|batch| Batcher::spawn(
+            log.fork(ea!(sys = "batch")),
+            signer.clone(),
+            issuer.clone(),
+            translog.clone(),
+            Duration::from_millis(batch.window_ms),
+            batch.max_size.unwrap_or(4096),
+        ));
+
+        // App + api server
+        tm.critical_task({
+            let log = log.fork(ea!(sys = "web"));
+            let tm = tm.clone();
+
+            struct Inner {
+                log: Log,
+                keys_dir: PathBuf,
+                stamper: Stamper,
+            }
+
+            let inner = Arc::new(Inner {
+                log: log.clone(),
+                keys_dir: config.keys_dir.clone(),
+                stamper: Stamper {
+                    signer: signer,
+                    issuer: issuer,
+                    batcher: batcher,
+                    translog: translog,
+                    tsa: tsa,
+                    sth: Mutex::new(None),
+                },
+            });
+            async move {
+                let server =
+                    Server::new(
+                        TcpListener::bind(config.web_bind_addr),
+                    ).run(
+                        Route::new()
+                            .nest(
+                                "/api",
+                                Route::new()
+                                    .nest("key", StaticFilesEndpoint::new(&config.keys_dir))
+                                    .at("keys", get({
+                                        #[handler]
+                                        async fn ep(Data(service): Data<&Arc<Inner>>) -> Response {
+                                            match keys::key_index(&service.keys_dir) {
+                                                Ok(index) => return Json(index).into_response(),
+                                                Err(e) => return ApiError::new(
+                                                    ApiErrorCode::Internal,
+                                                    e,
+                                                ).response(&service.log, "Error building key index"),
+                                            }
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("health", get({
+                                        #[handler]
+                                        async fn ep(Data(service): Data<&Arc<Inner>>) -> Response {
+                                            let clock = service.stamper.issuer.clock.status();
+                                            let status = match clock.ok {
+                                                true => StatusCode::OK,
+                                                false => StatusCode::SERVICE_UNAVAILABLE,
+                                            };
+                                            return Json(SerialHealth { clock: clock })
+                                                .with_status(status)
+                                                .into_response();
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("stamp/:hash", get({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            Path(hash): Path<String>,
+                                            Query(query): Query<StampQuery>,
+                                        ) -> Response {
+                                            let algorithm = match parse_hash(query.algorithm.as_deref(), &hash) {
+                                                Ok(a) => a,
+                                                Err((code, message)) => return error_response(code, message),
+                                            };
+                                            if let Some(nonce) = &query.nonce {
+                                                if nonce.is_empty() || nonce.len() > 128 ||
+                                                    !nonce.bytes().all(|b| b.is_ascii_graphic()) {
+                                                    return error_response(
+                                                        ApiErrorCode::BadNonce,
+                                                        "Nonce must be 1 to 128 printable ascii characters".to_string(),
+                                                    );
+                                                }
+                                            }
+                                            match service
+                                                .stamper
+                                                .stamp(
+                                                    algorithm,
+                                                    hash,
+                                                    query.blinded.unwrap_or(false),
+                                                    query.nonce,
+                                                    query.idempotent.unwrap_or(false),
+                                                )
+                                                .await {
+                                                Ok(entry) => return stamp_response(entry.stamp_file),
+                                                Err(e) => return e.response(&service.log, "Error issuing stamp"),
+                                            }
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("stamps/:hash", get({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            Path(hash): Path<String>,
+                                            Query(query): Query<StampsQuery>,
+                                        ) -> Response {
+                                            let algorithm = match parse_hash(query.algorithm.as_deref(), &hash) {
+                                                Ok(a) => a,
+                                                Err((code, message)) => return error_response(code, message),
+                                            };
+                                            let translog = service.stamper.translog.lock().await;
+                                            return Json(
+                                                translog
+                                                    .stamps_for(algorithm, &hash)
+                                                    .into_iter()
+                                                    .map(|(_, e)| e)
+                                                    .collect::<Vec<_>>(),
+                                            ).into_response();
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("earliest/:hash", get({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            Path(hash): Path<String>,
+                                            Query(query): Query<StampsQuery>,
+                                        ) -> Response {
+                                            let algorithm = match parse_hash(query.algorithm.as_deref(), &hash) {
+                                                Ok(a) => a,
+                                                Err((code, message)) => return error_response(code, message),
+                                            };
+                                            let Some((leaf_index, entry)) =
+                                                service
+                                                    .stamper
+                                                    .translog
+                                                    .lock()
+                                                    .await
+                                                    .stamps_for(algorithm, &hash)
+                                                    .first()
+                                                    .map(|(i, e)| (*i, (*e).clone())) else {
+                                                    return error_response(
+                                                        ApiErrorCode::NotStamped,
+                                                        ApiErrorCode::NotStamped.to_string(),
+                                                    );
+                                                };
+
+                                            // The log only grows, so the entry is in any tree head made after
+                                            let sth = service.stamper.signed_tree_head().await;
+                                            let (tree_size, tree_head) = match sth {
+                                                Ok(sth) => sth,
+                                                Err(e) => return e.response(
+                                                    &service.log,
+                                                    "Error producing signed tree head",
+                                                ),
+                                            };
+                                            let translog = service.stamper.translog.lock().await;
+                                            let Some(path) = translog.inclusion_proof(leaf_index, tree_size) else {
+                                                return ApiError::new(
+                                                    ApiErrorCode::Internal,
+                                                    loga::err("Earliest entry is missing from the tree head"),
+                                                ).response(&service.log, "Error producing inclusion proof");
+                                            };
+                                            return Json(SerialEarliest {
+                                                entry: entry,
+                                                inclusion: InclusionProof {
+                                                    leaf_index: leaf_index,
+                                                    tree_size: tree_size,
+                                                    path: path.iter().map(hex::encode).collect(),
+                                                },
+                                                tree_head: tree_head,
+                                            }).into_response();
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("tsp", post({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            req: &Request,
+                                            body: Vec<u8>,
+                                        ) -> Response {
+                                            let Some(tsa) = &service.stamper.tsa else {
+                                                return StatusCode::NOT_FOUND.into_response();
+                                            };
+                                            if req.content_type() != Some("application/timestamp-query") {
+                                                return StatusCode::UNSUPPORTED_MEDIA_TYPE.into_response();
+                                            }
+                                            let resp = match tsp::parse_request(&body).and_then(|r| {
+                                                tsa.check_policy(&r)?;
+                                                Ok(r)
+                                            }) {
+                                                Ok(r) => match service.stamper.timestamp(tsa, r).await {
+                                                    Ok(r) => r,
+                                                    Err(e) => {
+                                                        service.log.warn_e(e, "Error issuing time-stamp token", ea!());
+                                                        tsp::failure_response(
+                                                            tsp::Failure::SystemFailure,
+                                                            "Error issuing token",
+                                                        )
+                                                    },
+                                                },
+                                                Err(f) => tsp::failure_response(f, "Bad request"),
+                                            };
+                                            return Response::builder().content_type("application/timestamp-reply").body(resp);
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("log/sth", get({
+                                        #[handler]
+                                        async fn ep(Data(service): Data<&Arc<Inner>>) -> Response {
+                                            match service.stamper.signed_tree_head().await {
+                                                Ok((_, sth)) => return Response::builder().body(sth),
+                                                Err(e) => return e.response(
+                                                    &service.log,
+                                                    "Error producing signed tree head",
+                                                ),
+                                            }
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("log/inclusion", get({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            Query(query): Query<InclusionQuery>,
+                                        ) -> Response {
+                                            let translog = service.stamper.translog.lock().await;
+                                            let Some(leaf_hash) =
+                                                hex::decode(
+                                                    &query.leaf_hash,
+                                                ).ok().and_then(|h| merkle::Hash::try_from(h).ok()) else {
+                                                    return StatusCode::BAD_REQUEST.into_response();
+                                                };
+                                            let Some(leaf_index) = translog.index_of(&leaf_hash) else {
+                                                return StatusCode::NOT_FOUND.into_response();
+                                            };
+                                            let Some(path) = translog.inclusion_proof(leaf_index, query.tree_size) else {
+                                                return StatusCode::NOT_FOUND.into_response();
+                                            };
+                                            return Json(InclusionProof {
+                                                leaf_index: leaf_index,
+                                                tree_size: query.tree_size,
+                                                path: path.iter().map(hex::encode).collect(),
+                                            }).into_response();
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("log/consistency", get({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            Query(query): Query<ConsistencyQuery>,
+                                        ) -> Response {
+                                            let translog = service.stamper.translog.lock().await;
+                                            let Some(path) = translog.consistency_proof(query.first, query.second) else {
+                                                return StatusCode::NOT_FOUND.into_response();
+                                            };
+                                            return Json(ConsistencyProof {
+                                                first: query.first,
+                                                second: query.second,
+                                                path: path.iter().map(hex::encode).collect(),
+                                            }).into_response();
+                                        }
+
+                                        ep
+                                    }))
+                                    .at("log/entries", get({
+                                        #[handler]
+                                        async fn ep(
+                                            Data(service): Data<&Arc<Inner>>,
+                                            Query(query): Query<EntriesQuery>,
+                                        ) -> Response {
+                                            let translog = service.stamper.translog.lock().await;
+                                            let entries = translog.entries();
+                                            let start = query.start as usize;
+                                            let end =
+                                                (query.end as usize)
+                                                    .min(entries.len())
+                                                    .min(start.saturating_add(1000));
+                                            let Some(entries) = entries.get(start .. end) else {
+                                                return StatusCode::NOT_FOUND.into_response();
+                                            };
+                                            return Json(entries).into_response();
+                                        }
+
+                                        ep
+                                    })),
+                            )
+                            .nest("/", StaticFilesEndpoint::new(&config.static_dir).index_file("index.html"))
+                            .with(AddData::new(inner)),
+                    );
+
+                select!{
+                    _ = tm.until_terminate() => {
+                        return Ok(());
+                    }
+                    r = server => {
+                        return r.log_context(&log, "Exited with error");
+                    }
+                }
+            }
+        });
+
+        // Wait for shutdown, cleanup
+        tm.join().await?;
+        return Ok(());
+    }
+
+    match inner().await {
+        Ok(_) => { },
+        Err(e) => {
+            fatal(e);
+        },
+    }
+}
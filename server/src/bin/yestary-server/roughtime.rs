@@ -0,0 +1,232 @@
+//! Gets stamp times attested by Roughtime servers before signing, see
+//! `shared::roughtime`.
+use std::time::Duration;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use loga::{
+    ea,
+    ResultContext,
+};
+use shared::{
+    api::ApiErrorCode,
+    roughtime::{
+        self,
+        Attestation,
+        SerialRoughtime,
+        NONCE_LEN,
+    },
+};
+use tokio::{
+    net::UdpSocket,
+    task::JoinSet,
+    time::timeout,
+};
+use crate::error::{
+    ApiError,
+    ResultApi,
+};
+
+/// Largest UDP payload; responses with long certificate chains or merkle paths
+/// can exceed the request size.
+const MAX_RESPONSE_LEN: usize = 65535;
+
+#[derive(Clone)]
+pub struct RoughtimeServer {
+    /// `host:port`
+    pub addr: String,
+    /// Ed25519 long-term public key
+    pub public_key: [u8; 32],
+}
+
+impl RoughtimeServer {
+    /// Ask the server to sign `nonce`, returning the checked response and the time
+    /// it vouches for.
+    pub async fn query(&self, nonce: &[u8; NONCE_LEN]) -> Result<(Vec<u8>, Attestation), loga::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await.context("Error opening Roughtime socket")?;
+        socket
+            .connect(&self.addr)
+            .await
+            .context_with("Error resolving Roughtime server", ea!(server = self.addr.clone()))?;
+        socket
+            .send(&roughtime::request(nonce))
+            .await
+            .context_with("Error sending Roughtime request", ea!(server = self.addr.clone()))?;
+        let mut response = vec![0u8; MAX_RESPONSE_LEN];
+        let size =
+            timeout(Duration::from_secs(5), socket.recv(&mut response))
+                .await
+                .map_err(
+                    |_| loga::err_with("Timed out waiting for Roughtime response", ea!(server = self.addr.clone())),
+                )?
+                .context_with("Error receiving Roughtime response", ea!(server = self.addr.clone()))?;
+        response.truncate(size);
+        let attestation =
+            roughtime::verify_response(
+                &self.public_key,
+                nonce,
+                &response,
+            ).map_err(|e| loga::err_with("Bad Roughtime response", ea!(server = self.addr.clone(), err = e)))?;
+        return Ok((response, attestation));
+    }
+}
+
+/// Asks every configured Roughtime server to attest each stamp before it's
+/// signed.
+pub struct Attester {
+    servers: Vec<RoughtimeServer>,
+    /// How many servers must attest a stamp for it to be issued
+    required: usize,
+}
+
+impl Attester {
+    /// `required` defaults to all of the servers.  Errors if more are required than
+    /// are configured, since no stamp could be issued.
+    pub fn new(servers: Vec<RoughtimeServer>, required: Option<usize>) -> Result<Attester, loga::Error> {
+        let required = required.unwrap_or(servers.len());
+        if required > servers.len() {
+            return Err(
+                loga::err_with(
+                    "More Roughtime attestations are required than there are servers",
+                    ea!(required = required.to_string(), servers = servers.len().to_string()),
+                ),
+            );
+        }
+        return Ok(Attester {
+            servers: servers,
+            required: required,
+        });
+    }
+
+    /// Ask every server to attest the canonical stamp `body`.  The nonce doesn't
+    /// cover the stamp time or chain link (see `shared::roughtime::stamp_nonce`), so
+    /// this can happen before they're settled; `Attested::agreeing` checks the
+    /// answers against the final time.
+    pub async fn attest(&self, body: &[u8]) -> Result<Attested<'_>, ApiError> {
+        let nonce = roughtime::stamp_nonce(body).unwrap();
+        let mut queries = JoinSet::new();
+        for (i, server) in self.servers.iter().enumerate() {
+            let server = server.clone();
+            queries.spawn(async move {
+                return (i, server.query(&nonce).await);
+            });
+        }
+        let mut responses = vec![];
+        let mut errors = vec![];
+        while let Some(query) = queries.join_next().await {
+            let (i, result) = query.context("Roughtime query panicked").api_code(ApiErrorCode::Internal)?;
+            match result {
+                Ok((response, attestation)) => responses.push((i, response, attestation)),
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        // Keep the configured order rather than the order they answered in
+        responses.sort_by_key(|(i, _, _)| *i);
+        return Ok(Attested {
+            attester: self,
+            responses: responses,
+            errors: errors,
+        });
+    }
+}
+
+/// The servers' answers for a stamp, not yet checked against its time.
+pub struct Attested<'a> {
+    attester: &'a Attester,
+    /// Server index, raw response, and the time it vouches for
+    responses: Vec<(usize, Vec<u8>, Attestation)>,
+    errors: Vec<String>,
+}
+
+impl Attested<'_> {
+    /// Attestations from every server that agrees with a stamp made at `time`.
+    /// Errors if too few do.
+    pub fn agreeing(&self, time: DateTime<Utc>) -> Result<Vec<SerialRoughtime>, ApiError> {
+        let mut attestations = vec![];
+        let mut errors = self.errors.clone();
+        for (i, response, attestation) in &self.responses {
+            let server = &self.attester.servers[*i];
+            if !attestation.agrees(time) {
+                errors.push(format!("{} attested {}", server.addr, attestation.midpoint.to_rfc3339()));
+                continue;
+            }
+            attestations.push(SerialRoughtime {
+                server: server.addr.clone(),
+                public_key: hex::encode(server.public_key),
+                response: hex::encode(response),
+            });
+        }
+        if attestations.len() < self.attester.required {
+            return Err(
+                ApiError::new(
+                    ApiErrorCode::RoughtimeUnavailable,
+                    loga::err_with("Too few Roughtime servers attested the stamp", ea!(errors = errors.join("; "))),
+                ),
+            );
+        }
+        return Ok(attestations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    };
+    use shared::{
+        api::ApiErrorCode,
+        roughtime::Attestation,
+    };
+    use super::{
+        Attested,
+        Attester,
+        RoughtimeServer,
+    };
+
+    fn servers(n: u8) -> Vec<RoughtimeServer> {
+        return (0 .. n).map(|i| RoughtimeServer {
+            addr: format!("roughtime{}.example.com:2002", i),
+            public_key: [i; 32],
+        }).collect();
+    }
+
+    #[test]
+    fn agreeing() {
+        let attester = Attester::new(servers(2), Some(1)).unwrap();
+        let time: DateTime<Utc> = "2026-10-17T03:00:00Z".parse().unwrap();
+        let attested = Attested {
+            attester: &attester,
+            responses: vec![(0, vec![0], Attestation {
+                midpoint: time,
+                radius: Duration::seconds(1),
+            }), (1, vec![1], Attestation {
+                midpoint: time + Duration::minutes(5),
+                radius: Duration::seconds(1),
+            })],
+            errors: vec![],
+        };
+        let Ok(attestations) = attested.agreeing(time + Duration::seconds(2)) else {
+            panic!("Too few attestations agreed");
+        };
+        assert_eq!(attestations.len(), 1);
+        assert_eq!(attestations[0].response, "00");
+
+        // Redated too far from what the servers saw
+        let Err(e) = attested.agreeing(time + Duration::minutes(2)) else {
+            panic!("Attested a time no server agreed with");
+        };
+        assert_eq!(e.code, ApiErrorCode::RoughtimeUnavailable);
+    }
+
+    #[test]
+    fn required() {
+        assert_eq!(Attester::new(servers(2), None).unwrap().required, 2);
+        assert_eq!(Attester::new(servers(2), Some(1)).unwrap().required, 1);
+        assert!(Attester::new(servers(2), Some(3)).is_err());
+        assert!(Attester::new(servers(0), Some(1)).is_err());
+    }
+}
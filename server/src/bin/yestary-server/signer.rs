@@ -0,0 +1,580 @@
+use std::{
+    fs,
+    io,
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    path::Path,
+    thread,
+};
+use chrono::Utc;
+use loga::{
+    ea,
+    ResultContext,
+};
+use openpgp_card::{
+    crypto_data::Hash,
+    OpenPgp,
+    OpenPgpTransaction,
+};
+use openpgp_card_pcsc::PcscBackend;
+use sequoia_openpgp::{
+    crypto::{
+        self,
+        mpi,
+    },
+    packet::{
+        key::{
+            PublicParts,
+            UnspecifiedRole,
+        },
+        Key,
+    },
+    types::HashAlgorithm,
+    parse::Parse,
+    policy::StandardPolicy,
+    serialize::stream::{
+        Armorer,
+        LiteralWriter,
+        Message,
+        Signer,
+    },
+    Cert,
+    Fingerprint,
+    KeyID,
+};
+use shared::{
+    api::ApiErrorCode,
+    keys::{
+        compromise_filename,
+        meta_filename,
+        SerialKeyMeta,
+    },
+};
+use tokio::sync::{
+    mpsc,
+    oneshot,
+};
+use crate::error::{
+    ApiError,
+    ResultApi,
+};
+
+/// Something that can produce the armored, signed message for a stamp payload.
+/// The http layer only sees this, so new key storage just needs a new
+/// implementation.  Errors carry the code clients see.
+pub trait StampSigner: Send {
+    fn sign_message(&mut self, data: &[u8]) -> Result<Vec<u8>, ApiError>;
+
+    /// Raw signature over an already computed digest, for non-OpenPGP formats
+    /// (RFC 3161).
+    fn sign_digest(&mut self, hash_algo: HashAlgorithm, digest: &[u8]) -> Result<mpi::Signature, ApiError>;
+
+    /// The public half of the signing key
+    fn public_key(&self) -> PublicKey;
+}
+
+pub type PublicKey = Key<PublicParts, UnspecifiedRole>;
+
+/// Write `data` as a literal packet signed by `signer`, armored.
+pub fn write_signed_message<S: crypto::Signer + Send + Sync>(signer: S, data: &[u8]) -> Result<Vec<u8>, loga::Error> {
+    let mut sink = vec![];
+    let mut message =
+        LiteralWriter::new(
+            Signer::new(
+                Armorer::new(Message::new(&mut sink))
+                    .build()
+                    .map_err(
+                        |e| loga::err_with("Failed to create armored message builder", ea!(err = e.to_string())),
+                    )?,
+                signer,
+            )
+                .build()
+                .map_err(|e| loga::err_with("Failed to create signer serializer", ea!(err = e.to_string())))?,
+        )
+            .build()
+            .map_err(|e| loga::err_with("Failed to create literal writer", ea!(err = e.to_string())))?;
+    io::copy(&mut &data[..], &mut message).context("Failed to sign data")?;
+    message.finalize().map_err(|e| loga::err_with("Failed to write data", ea!(err = e.to_string())))?;
+    return Ok(sink);
+}
+
+/// Signs with the signing key on a specific OpenPGP card (Yubikey, etc) via
+/// pcsc. The card connection is held open between signatures and reopened if it
+/// fails (ex: the card was removed and reinserted).  The pin is verified once per
+/// connection, or before every signature if the card's pin policy requires it.
+pub struct CardStampSigner {
+    card_config: CardConfig,
+    card: Option<OpenPgp>,
+    /// The card accepted the pin on this connection and will sign without it
+    unlocked: bool,
+    /// The card rejected the configured pin.  Retrying can't help and uses up the
+    /// card's few pin tries, so signing is refused until the server restarts.
+    pin_rejected: bool,
+    public_key: PublicKey,
+}
+
+enum CardOp<'a> {
+    Message(&'a [u8]),
+    Digest(HashAlgorithm, &'a [u8]),
+}
+
+enum CardSigned {
+    Message(Vec<u8>),
+    Digest(mpi::Signature),
+}
+
+struct CardConfig {
+    ident: String,
+    fingerprint: Fingerprint,
+    pin: String,
+}
+
+impl CardStampSigner {
+    /// Checks that the card is present, holds the expected signing key, that the
+    /// key is the one published in `keys_dir`, and that the pin works. Any failure
+    /// stops the server at boot rather than failing (or signing wrong) later.
+    pub fn new(ident: String, fingerprint: Fingerprint, pin: String, keys_dir: &Path) -> Result<Self, loga::Error> {
+        let public_key = check_published(keys_dir, &fingerprint)?;
+        let card_config = CardConfig {
+            ident: ident,
+            fingerprint: fingerprint,
+            pin: pin,
+        };
+        let mut card = card_config.open_card().map_err(|e| e.err)?;
+        let mut transaction = card.transaction().context("Failed to start card transaction")?;
+        card_config.check_card(&mut transaction).map_err(|e| e.err)?;
+        let unlocked = card_config.unlock(&mut transaction).map_err(|e| e.err)?;
+        drop(transaction);
+        return Ok(CardStampSigner {
+            card_config: card_config,
+            card: Some(card),
+            unlocked: unlocked,
+            pin_rejected: false,
+            public_key: public_key,
+        });
+    }
+
+    fn sign(&mut self, op: CardOp) -> Result<CardSigned, ApiError> {
+        if self.pin_rejected {
+            return Err(
+                ApiError::new(
+                    ApiErrorCode::SigningFailed,
+                    loga::err_with(
+                        "Card rejected the configured pin, fix it and restart",
+                        ea!(card = self.card_config.ident.clone()),
+                    ),
+                ),
+            );
+        }
+        match self.try_sign(&op) {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                // Start the next signature with a fresh connection
+                let had_card = self.card.take().is_some();
+                self.unlocked = false;
+
+                // Maybe the card was reinserted or the reader reset, reconnect and retry
+                // once.  Anything else (especially a pin failure) would just fail again.
+                if !had_card || e.code != ApiErrorCode::CardUnavailable {
+                    return Err(e);
+                }
+                return self.try_sign(&op);
+            },
+        }
+    }
+
+    fn try_sign(&mut self, op: &CardOp) -> Result<CardSigned, ApiError> {
+        if self.card.is_none() {
+            self.card = Some(self.card_config.open_card()?);
+            self.unlocked = false;
+        }
+        let card = self.card.as_mut().unwrap();
+        let mut transaction =
+            card
+                .transaction()
+                .context("Failed to start card transaction")
+                .api_code(ApiErrorCode::CardUnavailable)?;
+        if !self.unlocked {
+            self.card_config.check_card(&mut transaction)?;
+            match self.card_config.unlock(&mut transaction) {
+                Ok(unlocked) => self.unlocked = unlocked,
+                Err(e) => {
+                    self.pin_rejected = e.code != ApiErrorCode::CardUnavailable;
+                    return Err(e);
+                },
+            }
+        }
+
+        let mut signer = CardKeySigner {
+            transaction: &mut transaction,
+            public_key: &self.public_key,
+        };
+        match op {
+            CardOp::Message(data) => return Ok(
+                CardSigned::Message(write_signed_message(signer, data).api_code(ApiErrorCode::SigningFailed)?),
+            ),
+            CardOp::Digest(hash_algo, digest) => return Ok(
+                CardSigned::Digest(
+                    crypto::Signer::sign(
+                        &mut signer,
+                        *hash_algo,
+                        digest,
+                    )
+                        .map_err(|e| loga::err_with("Failed to sign digest with card", ea!(err = e.to_string())))
+                        .api_code(ApiErrorCode::SigningFailed)?,
+                ),
+            ),
+        }
+    }
+}
+
+/// Signs with the card's signing key in a transaction on a card that's already
+/// unlocked, possibly in an earlier transaction.  `openpgp_card_sequoia`'s
+/// signer only works if the pin was verified in the same transaction.  Touch
+/// confirmation must be off for the key, a signature waiting for a touch fails.
+struct CardKeySigner<'a, 'b> {
+    transaction: &'a mut OpenPgpTransaction<'b>,
+    public_key: &'a PublicKey,
+}
+
+impl crypto::Signer for CardKeySigner<'_, '_> {
+    fn public(&self) -> &PublicKey {
+        return self.public_key;
+    }
+
+    fn sign(&mut self, hash_algo: HashAlgorithm, digest: &[u8]) -> sequoia_openpgp::Result<mpi::Signature> {
+        match self.public_key.mpis() {
+            mpi::PublicKey::RSA { .. } => {
+                let hash = match hash_algo {
+                    HashAlgorithm::SHA256 => Hash::SHA256(digest.try_into()?),
+                    HashAlgorithm::SHA384 => Hash::SHA384(digest.try_into()?),
+                    HashAlgorithm::SHA512 => Hash::SHA512(digest.try_into()?),
+                    a => return Err(
+                        sequoia_openpgp::Error::InvalidArgument(format!("Card can't sign {} digests", a)).into(),
+                    ),
+                };
+                let signature = self.transaction.signature_for_hash(hash)?;
+                return Ok(mpi::Signature::RSA { s: mpi::MPI::new(&signature) });
+            },
+            mpi::PublicKey::EdDSA { .. } => {
+                let signature = self.transaction.signature_for_hash(Hash::EdDSA(digest))?;
+                let (r, s) = signature.split_at(signature.len() / 2);
+                return Ok(mpi::Signature::EdDSA {
+                    r: mpi::MPI::new(r),
+                    s: mpi::MPI::new(s),
+                });
+            },
+            mpi::PublicKey::ECDSA { .. } => {
+                let signature = self.transaction.signature_for_hash(Hash::ECDSA(digest))?;
+                let (r, s) = signature.split_at(signature.len() / 2);
+                return Ok(mpi::Signature::ECDSA {
+                    r: mpi::MPI::new(r),
+                    s: mpi::MPI::new(s),
+                });
+            },
+            _ => return Err(
+                sequoia_openpgp::Error::InvalidArgument("Unsupported card signing key algorithm".to_string()).into(),
+            ),
+        }
+    }
+}
+
+impl CardConfig {
+    fn open_card(&self) -> Result<OpenPgp, ApiError> {
+        return Ok(
+            OpenPgp::new(
+                PcscBackend::open_by_ident(&self.ident, None)
+                    .context_with("Error opening configured card, is it attached?", ea!(card = self.ident.clone()))
+                    .api_code(ApiErrorCode::CardUnavailable)?,
+            ),
+        );
+    }
+
+    /// Confirm the card's signing key is the configured one.
+    fn check_card(&self, transaction: &mut OpenPgpTransaction) -> Result<(), ApiError> {
+        let card_fingerprint =
+            transaction
+                .application_related_data()
+                .and_then(|data| data.fingerprints())
+                .context_with("Error reading key fingerprints from card", ea!(card = self.ident.clone()))
+                .api_code(ApiErrorCode::CardUnavailable)?
+                .signature()
+                .map(|f| Fingerprint::from_bytes(f.as_bytes()))
+                .ok_or_else(|| loga::err_with("Card has no signing key", ea!(card = self.ident.clone())))
+                .api_code(ApiErrorCode::SigningFailed)?;
+        if card_fingerprint != self.fingerprint {
+            return Err(
+                ApiError::new(
+                    ApiErrorCode::SigningFailed,
+                    loga::err_with(
+                        "Card signing key doesn't match configured fingerprint",
+                        ea!(
+                            card = self.ident.clone(),
+                            expected = self.fingerprint.to_hex(),
+                            found = card_fingerprint.to_hex()
+                        ),
+                    ),
+                ),
+            );
+        }
+        return Ok(());
+    }
+
+    /// Unlock the card for signing.  Returns whether it stays unlocked for more
+    /// than one signature, which depends on the card's pin policy.  Any error
+    /// other than `CardUnavailable` means the card rejected the pin.
+    fn unlock(&self, transaction: &mut OpenPgpTransaction) -> Result<bool, ApiError> {
+        let valid_once =
+            transaction
+                .application_related_data()
+                .and_then(|data| data.pw_status_bytes())
+                .context_with("Error reading pin status from card", ea!(card = self.ident.clone()))
+                .api_code(ApiErrorCode::CardUnavailable)?
+                .pw1_cds_valid_once();
+        if let Err(e) = transaction.verify_pw1_sign(self.pin.as_bytes()) {
+            // A blocked pin needs the admin pin to reset, so say so rather than just
+            // failing
+            let locked =
+                transaction
+                    .application_related_data()
+                    .and_then(|data| data.pw_status_bytes())
+                    .map(|s| s.err_count_pw1() == 0)
+                    .unwrap_or(false);
+            return Err(
+                ApiError::new(
+                    if locked {
+                        ApiErrorCode::PinLocked
+                    } else {
+                        ApiErrorCode::SigningFailed
+                    },
+                    loga::err_with(
+                        "Error unlocking card with pin",
+                        ea!(card = self.ident.clone(), err = e.to_string(), locked = locked.to_string()),
+                    ),
+                ),
+            );
+        }
+        return Ok(!valid_once);
+    }
+}
+
+/// Make sure the signing key has been published - the verifier fetches the cert
+/// from `keys_dir` by the key id in the signature, so it must exist and contain the
+/// key. Returns the published key.
+fn check_published(keys_dir: &Path, fingerprint: &Fingerprint) -> Result<PublicKey, loga::Error> {
+    let path = keys_dir.join(KeyID::from(fingerprint).to_hex().to_lowercase());
+    let cert =
+        Cert::from_file(
+            &path,
+        ).map_err(
+            |e| loga::err_with(
+                "Error reading published cert for signing key",
+                ea!(path = path.to_string_lossy().to_string(), err = e.to_string()),
+            ),
+        )?;
+    let Some(key) = cert.keys().find(|k| &k.fingerprint() == fingerprint) else {
+        return Err(
+            loga::err_with(
+                "Published cert doesn't contain the signing key",
+                ea!(path = path.to_string_lossy().to_string(), fingerprint = fingerprint.to_hex()),
+            ),
+        );
+    };
+    return Ok(key.key().clone());
+}
+
+impl StampSigner for CardStampSigner {
+    fn sign_message(&mut self, data: &[u8]) -> Result<Vec<u8>, ApiError> {
+        match self.sign(CardOp::Message(data))? {
+            CardSigned::Message(m) => return Ok(m),
+            CardSigned::Digest(_) => unreachable!(),
+        }
+    }
+
+    fn sign_digest(&mut self, hash_algo: HashAlgorithm, digest: &[u8]) -> Result<mpi::Signature, ApiError> {
+        match self.sign(CardOp::Digest(hash_algo, digest))? {
+            CardSigned::Digest(s) => return Ok(s),
+            CardSigned::Message(_) => unreachable!(),
+        }
+    }
+
+    fn public_key(&self) -> PublicKey {
+        return self.public_key.clone();
+    }
+}
+
+enum SignRequest {
+    Message {
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<Vec<u8>, ApiError>>,
+    },
+    Digest {
+        hash_algo: HashAlgorithm,
+        digest: Vec<u8>,
+        reply: oneshot::Sender<Result<mpi::Signature, ApiError>>,
+    },
+}
+
+/// Handle to a dedicated thread that owns the signer. Card I/O is blocking and
+/// the card can only do one thing at a time, so all signing goes through a
+/// bounded queue to that thread.
+#[derive(Clone)]
+pub struct SignerHandle {
+    queue: mpsc::Sender<SignRequest>,
+    pub public_key: PublicKey,
+}
+
+/// Make sure the seal key's lifecycle metadata (published in `keys_dir` next to
+/// the cert) allows it to sign right now, and that no compromise has been
+/// declared for it.
+fn check_active(keys_dir: &Path, keyid: &str) -> Result<(), loga::Error> {
+    let compromise_path = keys_dir.join(compromise_filename(keyid));
+    if compromise_path.exists() {
+        return Err(
+            loga::err_with(
+                "Seal key has been declared compromised, refusing to sign",
+                ea!(path = compromise_path.to_string_lossy().to_string()),
+            ),
+        );
+    }
+    let meta_path = keys_dir.join(meta_filename(keyid));
+    let meta: SerialKeyMeta =
+        serde_json::from_slice(
+            &fs::read(
+                &meta_path,
+            ).context_with("Error reading seal key metadata", ea!(path = meta_path.to_string_lossy().to_string()))?,
+        ).context_with("Error parsing seal key metadata", ea!(path = meta_path.to_string_lossy().to_string()))?;
+    meta.check(Utc::now()).context("Seal key isn't active, refusing to sign")?;
+    return Ok(());
+}
+
+/// A panic while signing fails that request rather than stopping the signer
+/// thread, which would stop all signing.
+fn supervised<T>(f: impl FnOnce() -> Result<T, ApiError>) -> Result<T, ApiError> {
+    return panic::catch_unwind(
+        AssertUnwindSafe(f),
+    ).unwrap_or_else(|_| Err(ApiError::new(ApiErrorCode::Internal, loga::err("Signer panicked"))));
+}
+
+impl SignerHandle {
+    /// The key's metadata is checked now (failing boot if the key can't sign) and
+    /// again before every signature, so retiring a key takes effect without a
+    /// restart.
+    pub fn spawn(
+        mut signer: Box<dyn StampSigner>,
+        keys_dir: &Path,
+        queue_size: usize,
+    ) -> Result<SignerHandle, loga::Error> {
+        let public_key = signer.public_key();
+        let keys_dir = keys_dir.to_path_buf();
+        let keyid = KeyID::from(public_key.fingerprint()).to_hex().to_lowercase();
+        check_active(&keys_dir, &keyid)?;
+        let (queue, mut queue_recv) = mpsc::channel::<SignRequest>(queue_size);
+        thread::spawn(move || {
+            while let Some(req) = queue_recv.blocking_recv() {
+                let active = check_active(&keys_dir, &keyid).api_code(ApiErrorCode::KeyInactive);
+                match req {
+                    SignRequest::Message { data, reply } => {
+                        _ = reply.send(active.and_then(|_| supervised(|| signer.sign_message(&data))));
+                    },
+                    SignRequest::Digest { hash_algo, digest, reply } => {
+                        _ = reply.send(active.and_then(|_| supervised(|| signer.sign_digest(hash_algo, &digest))));
+                    },
+                }
+            }
+        });
+        return Ok(SignerHandle {
+            queue: queue,
+            public_key: public_key,
+        });
+    }
+
+    pub async fn sign_message(&self, data: Vec<u8>) -> Result<Vec<u8>, ApiError> {
+        let (reply, reply_recv) = oneshot::channel();
+        self.queue.send(SignRequest::Message {
+            data: data,
+            reply: reply,
+        }).await.map_err(|_| ApiError::new(ApiErrorCode::Internal, loga::err("Signer thread exited")))?;
+        return reply_recv.await.context("Signer thread dropped request").api_code(ApiErrorCode::Internal)?;
+    }
+
+    pub async fn sign_digest(&self, hash_algo: HashAlgorithm, digest: Vec<u8>) -> Result<mpi::Signature, ApiError> {
+        let (reply, reply_recv) = oneshot::channel();
+        self.queue.send(SignRequest::Digest {
+            hash_algo: hash_algo,
+            digest: digest,
+            reply: reply,
+        }).await.map_err(|_| ApiError::new(ApiErrorCode::Internal, loga::err("Signer thread exited")))?;
+        return reply_recv.await.context("Signer thread dropped request").api_code(ApiErrorCode::Internal)?;
+    }
+}
+
+/// Signs with an unencrypted secret key (TSK) loaded from disk. For development,
+/// CI, and staging where there's no card.
+pub struct SoftwareStampSigner {
+    keypair: crypto::KeyPair,
+}
+
+impl SoftwareStampSigner {
+    pub fn new(tsk_path: &Path) -> Result<Self, loga::Error> {
+        let cert =
+            Cert::from_file(
+                tsk_path,
+            ).map_err(
+                |e| loga::err_with(
+                    "Error reading TSK",
+                    ea!(path = tsk_path.to_string_lossy().to_string(), err = e.to_string()),
+                ),
+            )?;
+        let policy = StandardPolicy::new();
+        let key =
+            cert
+                .keys()
+                .with_policy(&policy, None)
+                .alive()
+                .revoked(false)
+                .for_signing()
+                .secret()
+                .next()
+                .ok_or_else(
+                    || loga::err_with(
+                        "TSK has no valid signing-capable secret key",
+                        ea!(path = tsk_path.to_string_lossy().to_string()),
+                    ),
+                )?;
+        let keypair =
+            key
+                .key()
+                .clone()
+                .into_keypair()
+                .map_err(
+                    |e| loga::err_with(
+                        "Error loading signing keypair from TSK (is the secret key encrypted?)",
+                        ea!(path = tsk_path.to_string_lossy().to_string(), err = e.to_string()),
+                    ),
+                )?;
+        return Ok(SoftwareStampSigner { keypair: keypair });
+    }
+}
+
+impl StampSigner for SoftwareStampSigner {
+    fn sign_message(&mut self, data: &[u8]) -> Result<Vec<u8>, ApiError> {
+        return write_signed_message(self.keypair.clone(), data).api_code(ApiErrorCode::SigningFailed);
+    }
+
+    fn sign_digest(&mut self, hash_algo: HashAlgorithm, digest: &[u8]) -> Result<mpi::Signature, ApiError> {
+        return crypto::Signer::sign(
+            &mut self.keypair,
+            hash_algo,
+            digest,
+        )
+            .map_err(|e| loga::err_with("Failed to sign digest", ea!(err = e.to_string())))
+            .api_code(ApiErrorCode::SigningFailed);
+    }
+
+    fn public_key(&self) -> PublicKey {
+        return self.keypair.public().clone();
+    }
+}
@@ -0,0 +1,369 @@
+use std::sync::Arc;
+use chrono::SubsecRound;
+use loga::ResultContext;
+use sequoia_openpgp::types;
+use shared::{
+    api::ApiErrorCode,
+    canonical,
+    chain::SerialLink,
+    hash::HashAlgorithm,
+    transparency::{
+        LogEntry,
+        SerialTreeHead,
+        StampFormat,
+    },
+    verify::parse_stamp,
+    SerialStamp,
+    STAMP_VERSION,
+};
+use tokio::sync::{
+    Mutex,
+    MutexGuard,
+};
+use crate::{
+    batch::Batcher,
+    clock::Clock,
+    error::{
+        ApiError,
+        ResultApi,
+    },
+    roughtime::Attester,
+    signer::SignerHandle,
+    transparency::TransparencyLog,
+    tsp::{
+        TimeStampReq,
+        Tsa,
+    },
+};
+
+/// Who issued a stamp, recorded in every stamp.  Serials and the chain continue
+/// from the transparency log, so they carry on across restarts.
+pub struct Issuer {
+    pub notary: Option<String>,
+    /// Hex fingerprint of the signing key
+    pub key: String,
+    /// Where stamp times come from
+    pub clock: Arc<Clock>,
+    /// Gets stamp times attested before signing, if configured
+    pub attester: Option<Attester>,
+    chain: Mutex<Chain>,
+}
+
+/// The next serial and the last signature, which the next one links to (see
+/// `shared::chain`).
+pub struct Chain {
+    next_serial: u64,
+    pub previous: Option<SerialLink>,
+}
+
+impl Chain {
+    /// The serial for the next signature.  It stays the next serial until
+    /// `extend` records a signature with it, so a signature that fails to sign or
+    /// log doesn't leave a gap.
+    pub fn next_serial(&self) -> u64 {
+        return self.next_serial;
+    }
+
+    /// Link the chain to a signature once it's logged
+    pub fn extend(&mut self, serial: u64, body: &[u8]) {
+        self.next_serial = serial + 1;
+        self.previous = Some(SerialLink::to(serial, body));
+    }
+}
+
+impl Issuer {
+    pub fn new(
+        notary: Option<String>,
+        key: String,
+        clock: Arc<Clock>,
+        attester: Option<Attester>,
+        translog: &TransparencyLog,
+    ) -> Result<Issuer, loga::Error> {
+        let previous = match translog.last_chained() {
+            Some(entry) => {
+                let parsed = parse_stamp(entry.stamp_file.as_bytes()).context("Error parsing last logged stamp")?;
+                Some(SerialLink::to(entry.stamp.serial.unwrap(), parsed.body()))
+            },
+            None => None,
+        };
+        return Ok(Issuer {
+            notary: notary,
+            key: key,
+            clock: clock,
+            attester: attester,
+            chain: Mutex::new(Chain {
+                next_serial: translog.last_serial().map_or(0, |s| s + 1),
+                previous: previous,
+            }),
+        });
+    }
+
+    /// Hold the chain while signing and logging, so signatures join it in order.
+    pub async fn chain(&self) -> MutexGuard<'_, Chain> {
+        return self.chain.lock().await;
+    }
+}
+
+/// Issues stamps: signs (directly or in a batch) and records them in the
+/// transparency log.
+pub struct Stamper {
+    pub signer: SignerHandle,
+    pub issuer: Arc<Issuer>,
+    pub batcher: Option<Batcher>,
+    pub translog: Arc<Mutex<TransparencyLog>>,
+    pub tsa: Option<Tsa>,
+    /// Most recent signed tree head, (tree size, armored message)
+    pub sth: Mutex<Option<(u64, String)>>,
+}
+
+impl Stamper {
+    /// Returns the log entry for the new stamp, which includes the stamp file.
+    /// `hash` must be a valid hex digest for `algorithm`.  If `blinded` it's a
+    /// commitment rather than a document hash (see `shared::blind`).  If
+    /// `idempotent` and a stamp was already issued for the hash, returns the
+    /// earliest one instead.
+    pub async fn stamp(
+        &self,
+        algorithm: HashAlgorithm,
+        hash: String,
+        blinded: bool,
+        nonce: Option<String>,
+        idempotent: bool,
+    ) -> Result<LogEntry, ApiError> {
+        match &self.batcher {
+            Some(batcher) => {
+                return batcher.stamp(algorithm, hash, blinded, nonce, idempotent).await;
+            },
+            None => {
+                // Checked again under the chain lock, this just saves attesting
+                if idempotent {
+                    if let Some(entry) = self.translog.lock().await.earliest_stamp(algorithm, &hash) {
+                        return Ok(entry.clone());
+                    }
+                }
+                let mut stamp = SerialStamp {
+                    version: STAMP_VERSION,
+                    algorithm: algorithm,
+                    hash: hash,
+                    blinded: blinded,
+                    stamp: self.issuer.clock.stamp_time()?,
+                    serial: None,
+                    notary: self.issuer.notary.clone(),
+                    key: Some(self.issuer.key.clone()),
+                    nonce: nonce,
+                    previous: None,
+                    roughtime: vec![],
+                };
+
+                // Slow Roughtime servers shouldn't hold up other stamps, so get the
+                // attestations before taking the chain lock.  They don't cover the time
+                // or chain link, which are settled under it.
+                let attested = match &self.issuer.attester {
+                    Some(attester) => Some(attester.attest(&canonical::to_vec(&stamp).unwrap()).await?),
+                    None => None,
+                };
+
+                // Direct stamps join the chain once logged.  Stamps are only logged
+                // under the chain lock, so the idempotent check can't race one.
+                let mut chain = self.issuer.chain().await;
+                if idempotent {
+                    if let Some(entry) = self.translog.lock().await.earliest_stamp(algorithm, &stamp.hash) {
+                        return Ok(entry.clone());
+                    }
+                }
+
+                // Another stamp may have joined the chain while attesting, redate this
+                // one so times never decrease along the chain
+                stamp.stamp = self.issuer.clock.stamp_time()?;
+                let serial = chain.next_serial();
+                stamp.serial = Some(serial);
+                stamp.previous = chain.previous.clone();
+                if let Some(attested) = attested {
+                    stamp.roughtime = attested.agreeing(stamp.stamp)?;
+                }
+                let body = canonical::to_vec(&stamp).unwrap();
+                let message = self.signer.sign_message(body.clone()).await?;
+                let entry = LogEntry {
+                    stamp: stamp,
+                    stamp_file: String::from_utf8(message)
+                        .context("Failed to convert armor into string")
+                        .api_code(ApiErrorCode::Internal)?,
+                    format: StampFormat::NotaryStamp,
+                };
+                self.record(&entry).await.api_code(ApiErrorCode::LogFailed)?;
+                chain.extend(serial, &body);
+                return Ok(entry);
+            },
+        }
+    }
+
+    /// Issue an RFC 3161 token for a validated request, returning the DER
+    /// TimeStampResp. Logged like any other stamp, with the imprint as the hash.
+    /// The token signs its TSTInfo rather than the logged stamp, so nothing could
+    /// link to it: tokens get no serial and stay out of the chain.
+    pub async fn timestamp(&self, tsa: &Tsa, req: TimeStampReq) -> Result<Vec<u8>, loga::Error> {
+        // Tokens only have second precision, keep the log entry in agreement
+        let stamp = SerialStamp {
+            version: STAMP_VERSION,
+            algorithm: req.message_imprint.algorithm().ok_or_else(|| loga::err("Unsupported imprint algorithm"))?,
+            hash: hex::encode(req.message_imprint.hashed_message.as_bytes()),
+            blinded: false,
+            stamp: self.issuer.clock.stamp_time().map_err(|e| e.err)?.trunc_subsecs(0),
+            serial: None,
+            notary: self.issuer.notary.clone(),
+            key: Some(self.issuer.key.clone()),
+            nonce: None,
+            previous: None,
+            roughtime: vec![],
+        };
+        let (tst_info, signed_attrs) = tsa.prepare(&req, stamp.stamp)?;
+        let signature =
+            self
+                .signer
+                .sign_digest(types::HashAlgorithm::SHA256, Tsa::signed_attrs_digest(&signed_attrs))
+                .await
+                .map_err(|e| e.err)
+                .context("Failed to sign time-stamp token")?;
+        let resp = tsa.response(&req, tst_info, signed_attrs, &signature, &self.signer.public_key)?;
+        self.record(&LogEntry {
+            stamp: stamp,
+            stamp_file: hex::encode(&resp),
+            format: StampFormat::Rfc3161,
+        }).await?;
+        return Ok(resp);
+    }
+
+    /// Don't hand out a stamp unless it's been logged
+    async fn record(&self, entry: &LogEntry) -> Result<(), loga::Error> {
+        self.translog.lock().await.append(entry.clone()).context("Failed to record stamp in transparency log")?;
+        return Ok(());
+    }
+
+    /// Armored signed `SerialTreeHead` for the current log, with its tree size.
+    /// Reuses the last tree head if nothing's been logged since.
+    pub async fn signed_tree_head(&self) -> Result<(u64, String), ApiError> {
+        // Held while signing, so requests for the same tree size share a signature
+        let mut sth = self.sth.lock().await;
+        let head = {
+            let translog = self.translog.lock().await;
+            let tree_size = translog.size();
+            if let Some((sth_size, sth)) = sth.as_ref() {
+                if *sth_size == tree_size {
+                    return Ok((tree_size, sth.clone()));
+                }
+            }
+            SerialTreeHead {
+                tree_size: tree_size,
+                root_hash: hex::encode(translog.root(tree_size).unwrap()),
+                timestamp: self.issuer.clock.stamp_time()?,
+            }
+        };
+        let signed =
+            String::from_utf8(self.signer.sign_message(canonical::to_vec(&head).unwrap()).await?)
+                .context("Failed to convert armor into string")
+                .api_code(ApiErrorCode::Internal)?;
+        *sth = Some((head.tree_size, signed.clone()));
+        return Ok((head.tree_size, signed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::Arc,
+    };
+    use sequoia_openpgp::{
+        cert::CertBuilder,
+        crypto::mpi,
+        types::HashAlgorithm,
+        KeyID,
+    };
+    use shared::{
+        api::ApiErrorCode,
+        keys::meta_filename,
+    };
+    use tokio::sync::Mutex;
+    use crate::{
+        clock::Clock,
+        error::ApiError,
+        signer::{
+            PublicKey,
+            SignerHandle,
+            StampSigner,
+        },
+        transparency::TransparencyLog,
+    };
+    use super::{
+        Issuer,
+        Stamper,
+    };
+
+    /// Fails its first signature, like a card pulled mid-request, then "signs" by
+    /// returning the payload as is
+    struct FlakySigner {
+        public_key: PublicKey,
+        failed: bool,
+    }
+
+    impl StampSigner for FlakySigner {
+        fn sign_message(&mut self, data: &[u8]) -> Result<Vec<u8>, ApiError> {
+            if !self.failed {
+                self.failed = true;
+                return Err(ApiError::new(ApiErrorCode::CardUnavailable, loga::err("Card removed")));
+            }
+            return Ok(data.to_vec());
+        }
+
+        fn sign_digest(&mut self, _hash_algo: HashAlgorithm, _digest: &[u8]) -> Result<mpi::Signature, ApiError> {
+            unreachable!();
+        }
+
+        fn public_key(&self) -> PublicKey {
+            return self.public_key.clone();
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_signature_keeps_serial() {
+        let dir = std::env::temp_dir().join(format!("yestary-stamp-{}-serial", std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, _) = CertBuilder::new().generate().unwrap();
+        let public_key = cert.primary_key().key().clone().role_into_unspecified();
+        let keyid = KeyID::from(public_key.fingerprint()).to_hex().to_lowercase();
+        fs::write(dir.join(meta_filename(&keyid)), r#"{"activated":"2023-08-01T00:00:00Z"}"#).unwrap();
+        let translog = TransparencyLog::open(&dir.join("log")).unwrap();
+        let issuer =
+            Issuer::new(
+                None,
+                public_key.fingerprint().to_hex(),
+                Clock::spawn(loga::new(loga::Level::Info), None, None),
+                None,
+                &translog,
+            ).unwrap();
+        let stamper = Stamper {
+            signer: SignerHandle::spawn(Box::new(FlakySigner {
+                public_key: public_key,
+                failed: false,
+            }), &dir, 1).unwrap(),
+            issuer: Arc::new(issuer),
+            batcher: None,
+            translog: Arc::new(Mutex::new(translog)),
+            tsa: None,
+            sth: Mutex::new(None),
+        };
+        let algorithm = shared::hash::HashAlgorithm::Sha256;
+        let hash = hex::encode([1; 32]);
+        let Err(e) = stamper.stamp(algorithm, hash.clone(), false, None, false).await else {
+            panic!("Stamp signed despite the signer failing");
+        };
+        assert_eq!(e.code, ApiErrorCode::CardUnavailable);
+        let Ok(entry) = stamper.stamp(algorithm, hash, false, None, false).await else {
+            panic!("Stamp failed after the signer recovered");
+        };
+        assert_eq!(entry.stamp.serial, Some(0));
+        assert_eq!(entry.stamp.previous, None);
+        _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    fs::{
+        File,
+        OpenOptions,
+    },
+    io::{
+        BufRead,
+        BufReader,
+        Write,
+    },
+    path::Path,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use loga::{
+    ea,
+    ResultContext,
+};
+use shared::{
+    hash::HashAlgorithm,
+    merkle,
+    transparency::{
+        LogEntry,
+        StampFormat,
+    },
+};
+
+/// Append-only log of every issued stamp, persisted as json lines. Entries are
+/// written and synced before the stamp is returned to the requester.  A trailing
+/// line without a newline is from a write that failed or was cut off, so it was
+/// never handed out and is dropped when the log is opened.
+pub struct TransparencyLog {
+    file: File,
+    entries: Vec<LogEntry>,
+    leaves: Vec<merkle::Hash>,
+    /// Indexes of the entries for each stamped hash
+    by_hash: HashMap<(HashAlgorithm, String), Vec<usize>>,
+}
+
+impl TransparencyLog {
+    pub fn open(path: &Path) -> Result<Self, loga::Error> {
+        let mut entries = vec![];
+        let mut leaves = vec![];
+        let mut by_hash = HashMap::<_, Vec<_>>::new();
+        let mut torn = None;
+        match File::open(path) {
+            Ok(f) => {
+                let mut reader = BufReader::new(f);
+                let mut len = 0u64;
+                let mut line = vec![];
+                for i in 0 .. {
+                    line.clear();
+                    let read =
+                        reader
+                            .read_until(b'\n', &mut line)
+                            .context_with(
+                                "Error reading transparency log",
+                                ea!(path = path.to_string_lossy().to_string()),
+                            )?;
+                    if read == 0 {
+                        break;
+                    }
+                    if line.last() != Some(&b'\n') {
+                        torn = Some(len);
+                        break;
+                    }
+                    len += read as u64;
+                    let entry: LogEntry =
+                        serde_json::from_slice(
+                            &line,
+                        ).context_with(
+                            "Corrupt transparency log entry",
+                            ea!(path = path.to_string_lossy().to_string(), line = (i + 1).to_string()),
+                        )?;
+                    leaves.push(entry.leaf_hash());
+                    by_hash.entry((entry.stamp.algorithm, entry.stamp.hash.clone())).or_default().push(entries.len());
+                    entries.push(entry);
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => { },
+            Err(e) => {
+                return Err(e).context_with(
+                    "Error opening transparency log",
+                    ea!(path = path.to_string_lossy().to_string()),
+                );
+            },
+        }
+        let file =
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context_with(
+                    "Error opening transparency log for writing",
+                    ea!(path = path.to_string_lossy().to_string()),
+                )?;
+        if let Some(len) = torn {
+            file
+                .set_len(len)
+                .context_with(
+                    "Error removing partial transparency log entry",
+                    ea!(path = path.to_string_lossy().to_string()),
+                )?;
+        }
+        return Ok(TransparencyLog {
+            file: file,
+            entries: entries,
+            leaves: leaves,
+            by_hash: by_hash,
+        });
+    }
+
+    /// Returns the index of the new entry
+    pub fn append(&mut self, entry: LogEntry) -> Result<u64, loga::Error> {
+        self.append_all(vec![entry])?;
+        return Ok(self.entries.len() as u64 - 1);
+    }
+
+    /// Append entries with a single write, so a batch is logged whole or not at
+    /// all
+    pub fn append_all(&mut self, entries: Vec<LogEntry>) -> Result<(), loga::Error> {
+        let mut lines = vec![];
+        for entry in &entries {
+            lines.extend(serde_json::to_vec(entry).unwrap());
+            lines.push(b'\n');
+        }
+        let len = self.file.metadata().context("Error reading transparency log size")?.len();
+        if let Err(e) = self.file.write_all(&lines).and_then(|_| self.file.sync_data()) {
+            // Don't leave a partial line for the next entry to be appended to.  If this
+            // fails too, `open` drops it.
+            _ = self.file.set_len(len);
+            return Err(e).context("Error writing transparency log entries");
+        }
+        for entry in entries {
+            self.leaves.push(entry.leaf_hash());
+            self.by_hash.entry((entry.stamp.algorithm, entry.stamp.hash.clone())).or_default().push(self.entries.len());
+            self.entries.push(entry);
+        }
+        return Ok(());
+    }
+
+    pub fn size(&self) -> u64 {
+        return self.entries.len() as u64;
+    }
+
+    pub fn entries(&self) -> &[LogEntry] {
+        return &self.entries;
+    }
+
+    /// Every stamp issued for `hash` with its log index, earliest first
+    pub fn stamps_for(&self, algorithm: HashAlgorithm, hash: &str) -> Vec<(u64, &LogEntry)> {
+        let mut out = match self.by_hash.get(&(algorithm, hash.to_string())) {
+            Some(indexes) => indexes.iter().map(|i| (*i as u64, &self.entries[*i])).collect::<Vec<_>>(),
+            None => vec![],
+        };
+        out.sort_by_key(|(_, e)| e.stamp.stamp);
+        return out;
+    }
+
+    /// The earliest `.notary_stamp` issued for `hash`
+    pub fn earliest_stamp(&self, algorithm: HashAlgorithm, hash: &str) -> Option<&LogEntry> {
+        return self
+            .stamps_for(algorithm, hash)
+            .into_iter()
+            .find(|(_, e)| e.format == StampFormat::NotaryStamp)
+            .map(|(_, e)| e);
+    }
+
+    /// The `.notary_stamp` with the highest serial, the end of the chain (see
+    /// `shared::chain`)
+    pub fn last_chained(&self) -> Option<&LogEntry> {
+        return self
+            .entries
+            .iter()
+            .filter(|e| e.format == StampFormat::NotaryStamp && e.stamp.serial.is_some())
+            .max_by_key(|e| e.stamp.serial);
+    }
+
+    /// The time of the latest stamp issued so far
+    pub fn last_stamp_time(&self) -> Option<DateTime<Utc>> {
+        return self.entries.iter().map(|e| e.stamp.stamp).max();
+    }
+
+    /// The highest stamp serial issued so far
+    pub fn last_serial(&self) -> Option<u64> {
+        return self.entries.iter().filter_map(|e| e.stamp.serial).max();
+    }
+
+    pub fn root(&self, tree_size: u64) -> Option<merkle::Hash> {
+        return Some(merkle::root(self.leaves.get(..tree_size as usize)?));
+    }
+
+    pub fn index_of(&self, leaf_hash: &merkle::Hash) -> Option<u64> {
+        return self.leaves.iter().position(|l| l == leaf_hash).map(|i| i as u64);
+    }
+
+    pub fn inclusion_proof(&self, leaf_index: u64, tree_size: u64) -> Option<Vec<merkle::Hash>> {
+        if leaf_index >= tree_size {
+            return None;
+        }
+        return Some(merkle::inclusion_proof(leaf_index as usize, self.leaves.get(..tree_size as usize)?));
+    }
+
+    pub fn consistency_proof(&self, first: u64, second: u64) -> Option<Vec<merkle::Hash>> {
+        if first == 0 || first > second {
+            return None;
+        }
+        return Some(merkle::consistency_proof(first as usize, self.leaves.get(..second as usize)?));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{
+            self,
+            File,
+            OpenOptions,
+        },
+        io::Write,
+        path::PathBuf,
+    };
+    use shared::transparency::LogEntry;
+    use super::TransparencyLog;
+
+    /// A fresh log file path for the test `name`
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("yestary-translog-{}-{}", std::process::id(), name));
+        _ = fs::remove_file(&path);
+        return path;
+    }
+
+    fn entry(i: u8) -> LogEntry {
+        return LogEntry {
+            stamp: serde_json::from_value(
+                serde_json::json!({
+                    "hash": hex::encode([i; 32]),
+                    "stamp": "2023-08-01T00:00:00Z"
+                }),
+            ).unwrap(),
+            stamp_file: format!("stamp {}", i),
+            format: Default::default(),
+        };
+    }
+
+    #[test]
+    fn torn_entry() {
+        let path = log_path("torn");
+        let mut log = TransparencyLog::open(&path).unwrap();
+        log.append_all(vec![entry(0), entry(1)]).unwrap();
+        drop(log);
+
+        // A write that was cut off partway through the next entry
+        let line = serde_json::to_vec(&entry(2)).unwrap();
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&line[.. line.len() / 2]).unwrap();
+        let mut log = TransparencyLog::open(&path).unwrap();
+        assert_eq!(log.size(), 2);
+        log.append(entry(3)).unwrap();
+        drop(log);
+        let log = TransparencyLog::open(&path).unwrap();
+        assert_eq!(log.entries().iter().map(|e| e.stamp_file.as_str()).collect::<Vec<_>>(), vec![
+            "stamp 0",
+            "stamp 1",
+            "stamp 3"
+        ]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_write() {
+        let path = log_path("failed");
+        let mut log = TransparencyLog::open(&path).unwrap();
+        log.append(entry(0)).unwrap();
+
+        // Writes to a read-only handle fail
+        log.file = File::open(&path).unwrap();
+        assert!(log.append_all(vec![entry(1), entry(2)]).is_err());
+        assert_eq!(log.size(), 1);
+        assert!(log.stamps_for(Default::default(), &hex::encode([1u8; 32])).is_empty());
+        drop(log);
+        assert_eq!(TransparencyLog::open(&path).unwrap().size(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,662 @@
+//! RFC 3161 time-stamp protocol: request parsing and token/response encoding.
+use std::{
+    fs,
+    path::Path,
+    sync::atomic::{
+        AtomicU64,
+        Ordering,
+    },
+    time::Duration,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use cms::{
+    cert::{
+        CertificateChoices,
+        IssuerAndSerialNumber,
+    },
+    content_info::{
+        CmsVersion,
+        ContentInfo,
+    },
+    signed_data::{
+        CertificateSet,
+        EncapsulatedContentInfo,
+        SignedAttributes,
+        SignedData,
+        SignerIdentifier,
+        SignerInfo,
+        SignerInfos,
+    },
+};
+use der::{
+    asn1::{
+        BitString,
+        GeneralizedTime,
+        Int,
+        Null,
+        ObjectIdentifier,
+        OctetString,
+        SetOfVec,
+        Uint,
+    },
+    Any,
+    Decode,
+    DecodePem,
+    Encode,
+    Sequence,
+    Tag,
+};
+use loga::{
+    ea,
+    ResultContext,
+};
+use sequoia_openpgp::crypto::mpi;
+use shared::hash::HashAlgorithm;
+use sha2::{
+    Digest,
+    Sha256,
+};
+use x509_cert::{
+    attr::Attribute,
+    ext::Extensions,
+    spki::AlgorithmIdentifierOwned,
+    Certificate,
+};
+use crate::signer::PublicKey;
+
+const ID_CT_TSTINFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.1.4");
+const ID_SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+const ID_CONTENT_TYPE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.3");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_SIGNING_CERTIFICATE_V2: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.2.47");
+const ID_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const SHA256_WITH_RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+
+/// Message imprint hash algorithms we accept, the ones a logged stamp can
+/// record
+const IMPRINT_ALGORITHMS: &[(ObjectIdentifier, HashAlgorithm)] =
+    &[
+        (ID_SHA256, HashAlgorithm::Sha256),
+        // sha512
+        (ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.3"), HashAlgorithm::Sha512),
+        // sha3-256
+        (ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.8"), HashAlgorithm::Sha3_256),
+    ];
+
+#[derive(Clone, Sequence)]
+pub struct MessageImprint {
+    pub hash_algorithm: AlgorithmIdentifierOwned,
+    pub hashed_message: OctetString,
+}
+
+impl MessageImprint {
+    /// `None` if the imprint algorithm isn't one we accept
+    pub fn algorithm(&self) -> Option<HashAlgorithm> {
+        return IMPRINT_ALGORITHMS.iter().find(|(oid, _)| *oid == self.hash_algorithm.oid).map(|(_, a)| *a);
+    }
+}
+
+#[derive(Sequence)]
+pub struct TimeStampReq {
+    pub version: u8,
+    pub message_imprint: MessageImprint,
+    pub req_policy: Option<ObjectIdentifier>,
+    pub nonce: Option<Int>,
+    #[asn1(default = "Default::default")]
+    pub cert_req: bool,
+    #[asn1(context_specific = "0", tag_mode = "IMPLICIT", optional = "true")]
+    pub extensions: Option<Extensions>,
+}
+
+#[derive(Sequence)]
+struct TstInfo {
+    version: u8,
+    policy: ObjectIdentifier,
+    message_imprint: MessageImprint,
+    serial_number: Uint,
+    gen_time: GeneralizedTime,
+    nonce: Option<Int>,
+}
+
+#[derive(Sequence)]
+struct PkiStatusInfo {
+    status: u8,
+    status_string: Option<Vec<String>>,
+    fail_info: Option<BitString>,
+}
+
+#[derive(Sequence)]
+struct TimeStampResp {
+    status: PkiStatusInfo,
+    time_stamp_token: Option<ContentInfo>,
+}
+
+/// ESSCertIDv2 with the default hash algorithm (sha256) and no issuer serial
+#[derive(Sequence)]
+struct EssCertIdV2 {
+    cert_hash: OctetString,
+}
+
+#[derive(Sequence)]
+struct SigningCertificateV2 {
+    certs: Vec<EssCertIdV2>,
+}
+
+#[derive(Sequence)]
+struct EcdsaSigValue {
+    r: Uint,
+    s: Uint,
+}
+
+#[derive(Sequence)]
+struct RsaPublicKey {
+    modulus: Uint,
+    public_exponent: Uint,
+}
+
+/// PKIFailureInfo bits
+#[derive(Clone, Copy, Debug)]
+#[allow(clippy::enum_variant_names)]
+pub enum Failure {
+    BadAlg = 0,
+    BadRequest = 2,
+    BadDataFormat = 5,
+    UnacceptedPolicy = 15,
+    UnacceptedExtension = 16,
+    SystemFailure = 25,
+}
+
+/// DER TimeStampResp rejecting the request
+pub fn failure_response(failure: Failure, text: &str) -> Vec<u8> {
+    let bit = failure as usize;
+    let mut bits = vec![0u8; bit / 8 + 1];
+    bits[bit / 8] |= 0x80 >> (bit % 8);
+    return TimeStampResp {
+        status: PkiStatusInfo {
+            // rejection
+            status: 2,
+            status_string: Some(vec![text.to_string()]),
+            fail_info: Some(BitString::new((7 - bit % 8) as u8, bits).unwrap()),
+        },
+        time_stamp_token: None,
+    }.to_der().unwrap();
+}
+
+pub fn parse_request(data: &[u8]) -> Result<TimeStampReq, Failure> {
+    let req = TimeStampReq::from_der(data).map_err(|_| Failure::BadDataFormat)?;
+    if req.version != 1 {
+        return Err(Failure::BadRequest);
+    }
+    if req.extensions.as_ref().map(|e| !e.is_empty()).unwrap_or(false) {
+        return Err(Failure::UnacceptedExtension);
+    }
+    let Some(algorithm) = req.message_imprint.algorithm() else {
+        return Err(Failure::BadAlg);
+    };
+    if req.message_imprint.hashed_message.as_bytes().len() != algorithm.digest_len() {
+        return Err(Failure::BadDataFormat);
+    }
+    return Ok(req);
+}
+
+/// The time-stamping authority identity: an X.509 certificate for the signing
+/// key, and the policy the tokens are issued under.
+pub struct Tsa {
+    cert: Certificate,
+    cert_hash: Vec<u8>,
+    policy: ObjectIdentifier,
+    serial_counter: AtomicU64,
+}
+
+impl Tsa {
+    /// Loads the TSA certificate (PEM or DER), see `new`.
+    pub fn load(cert_path: &Path, policy: &str, public_key: &PublicKey) -> Result<Tsa, loga::Error> {
+        let data =
+            fs::read(
+                cert_path,
+            ).context_with("Error reading TSA certificate", ea!(path = cert_path.to_string_lossy().to_string()))?;
+        let cert = if data.starts_with(b"-----BEGIN") {
+            Certificate::from_pem(&data)
+        } else {
+            Certificate::from_der(&data)
+        }.map_err(
+            |e| loga::err_with(
+                "Error parsing TSA certificate",
+                ea!(path = cert_path.to_string_lossy().to_string(), err = e.to_string()),
+            ),
+        )?;
+        return Tsa::new(cert, policy, public_key);
+    }
+
+    /// Checks the certificate is for the signing key.
+    pub fn new(cert: Certificate, policy: &str, public_key: &PublicKey) -> Result<Tsa, loga::Error> {
+        check_cert_key(&cert, public_key)?;
+        let policy =
+            ObjectIdentifier::new(
+                policy,
+            ).map_err(|e| loga::err_with("Invalid TSA policy OID", ea!(policy = policy.to_string(), err = e.to_string())))?;
+        return Ok(Tsa {
+            cert_hash: Sha256::digest(cert.to_der().unwrap()).to_vec(),
+            cert: cert,
+            policy: policy,
+            serial_counter: AtomicU64::new(0),
+        });
+    }
+
+    pub fn check_policy(&self, req: &TimeStampReq) -> Result<(), Failure> {
+        match req.req_policy {
+            Some(p) if p != self.policy => return Err(Failure::UnacceptedPolicy),
+            _ => return Ok(()),
+        }
+    }
+
+    /// Build the DER TSTInfo and the signed attributes over it. The signature
+    /// goes over the sha256 of the signed attributes' DER.
+    pub fn prepare(
+        &self,
+        req: &TimeStampReq,
+        time: DateTime<Utc>,
+    ) -> Result<(Vec<u8>, SignedAttributes), loga::Error> {
+        // Unique: issue time plus a counter for tokens within the same nanosecond
+        let mut serial = vec![];
+        serial.extend(time.timestamp_nanos_opt().unwrap_or_default().to_be_bytes());
+        serial.extend(self.serial_counter.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+        let tst_info = TstInfo {
+            version: 1,
+            policy: self.policy,
+            message_imprint: req.message_imprint.clone(),
+            serial_number: Uint::new(&serial).unwrap(),
+            gen_time: GeneralizedTime::from_unix_duration(
+                Duration::from_secs(time.timestamp() as u64),
+            ).context("Time not representable in token")?,
+            nonce: req.nonce.clone(),
+        }.to_der().context("Error encoding TSTInfo")?;
+        let attr = |oid: ObjectIdentifier, value: Any| Attribute {
+            oid: oid,
+            values: SetOfVec::try_from(vec![value]).unwrap(),
+        };
+        let signed_attrs =
+            SetOfVec::try_from(
+                vec![
+                    attr(ID_CONTENT_TYPE, Any::encode_from(&ID_CT_TSTINFO).unwrap()),
+                    attr(
+                        ID_MESSAGE_DIGEST,
+                        Any::encode_from(&OctetString::new(Sha256::digest(&tst_info).to_vec()).unwrap()).unwrap(),
+                    ),
+                    attr(
+                        ID_SIGNING_CERTIFICATE_V2,
+                        Any::encode_from(
+                            &SigningCertificateV2 {
+                                certs: vec![EssCertIdV2 { cert_hash: OctetString::new(self.cert_hash.clone()).unwrap() }],
+                            },
+                        ).unwrap(),
+                    )
+                ],
+            ).context("Error building signed attributes")?;
+        return Ok((tst_info, signed_attrs));
+    }
+
+    /// The digest to sign for the signed attributes
+    pub fn signed_attrs_digest(signed_attrs: &SignedAttributes) -> Vec<u8> {
+        return Sha256::digest(signed_attrs.to_der().unwrap()).to_vec();
+    }
+
+    /// DER TimeStampResp granting the request with the signed token
+    pub fn response(
+        &self,
+        req: &TimeStampReq,
+        tst_info: Vec<u8>,
+        signed_attrs: SignedAttributes,
+        signature: &mpi::Signature,
+        public_key: &PublicKey,
+    ) -> Result<Vec<u8>, loga::Error> {
+        let (signature_algorithm, signature) = cms_signature(signature, public_key)?;
+        let sha256 = AlgorithmIdentifierOwned {
+            oid: ID_SHA256,
+            parameters: None,
+        };
+        let signed_data = SignedData {
+            version: CmsVersion::V3,
+            digest_algorithms: SetOfVec::try_from(vec![sha256.clone()]).unwrap(),
+            encap_content_info: EncapsulatedContentInfo {
+                econtent_type: ID_CT_TSTINFO,
+                econtent: Some(Any::new(Tag::OctetString, tst_info).unwrap()),
+            },
+            certificates: match req.cert_req {
+                true => Some(
+                    CertificateSet(
+                        SetOfVec::try_from(vec![CertificateChoices::Certificate(self.cert.clone())]).unwrap(),
+                    ),
+                ),
+                false => None,
+            },
+            crls: None,
+            signer_infos: SignerInfos(SetOfVec::try_from(vec![SignerInfo {
+                version: CmsVersion::V1,
+                sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+                    issuer: self.cert.tbs_certificate.issuer.clone(),
+                    serial_number: self.cert.tbs_certificate.serial_number.clone(),
+                }),
+                digest_alg: sha256,
+                signed_attrs: Some(signed_attrs),
+                signature_algorithm: signature_algorithm,
+                signature: OctetString::new(signature).unwrap(),
+                unsigned_attrs: None,
+            }]).unwrap()),
+        };
+        return TimeStampResp {
+            status: PkiStatusInfo {
+                // granted
+                status: 0,
+                status_string: None,
+                fail_info: None,
+            },
+            time_stamp_token: Some(ContentInfo {
+                content_type: ID_SIGNED_DATA,
+                content: Any::encode_from(&signed_data).context("Error encoding signed data")?,
+            }),
+        }.to_der().context("Error encoding time-stamp response");
+    }
+}
+
+/// Make sure the certificate's key is the signing key, otherwise the tokens won't
+/// verify.
+fn check_cert_key(cert: &Certificate, public_key: &PublicKey) -> Result<(), loga::Error> {
+    let spki = cert.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes();
+    let matches = match public_key.mpis() {
+        mpi::PublicKey::RSA { n, .. } => {
+            let cert_key = RsaPublicKey::from_der(spki).context("Error parsing TSA certificate RSA key")?;
+            cert_key.modulus.as_bytes() == n.value()
+        },
+        mpi::PublicKey::ECDSA { q, .. } => spki == q.value(),
+        _ => return Err(
+            loga::err_with(
+                "Signing key algorithm not supported for RFC 3161 tokens, use an RSA or ECDSA key",
+                ea!(algorithm = public_key.pk_algo().to_string()),
+            ),
+        ),
+    };
+    if !matches {
+        return Err(loga::err("TSA certificate public key doesn't match the signing key"));
+    }
+    return Ok(());
+}
+
+/// Convert an OpenPGP signature to CMS form
+fn cms_signature(
+    signature: &mpi::Signature,
+    public_key: &PublicKey,
+) -> Result<(AlgorithmIdentifierOwned, Vec<u8>), loga::Error> {
+    match (signature, public_key.mpis()) {
+        (mpi::Signature::RSA { s }, mpi::PublicKey::RSA { n, .. }) => {
+            // OpenPGP strips leading zeros, CMS wants the full modulus length
+            let mut out = vec![0u8; n.value().len().saturating_sub(s.value().len())];
+            out.extend(s.value());
+            return Ok((AlgorithmIdentifierOwned {
+                oid: SHA256_WITH_RSA_ENCRYPTION,
+                parameters: Some(Any::encode_from(&Null).unwrap()),
+            }, out));
+        },
+        (mpi::Signature::ECDSA { r, s }, _) => {
+            return Ok((AlgorithmIdentifierOwned {
+                oid: ECDSA_WITH_SHA256,
+                parameters: None,
+            }, EcdsaSigValue {
+                r: Uint::new(r.value()).context("Invalid ECDSA signature r")?,
+                s: Uint::new(s.value()).context("Invalid ECDSA signature s")?,
+            }.to_der().context("Error encoding ECDSA signature")?));
+        },
+        _ => return Err(loga::err("Signature algorithm not supported for RFC 3161 tokens")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        time::Duration,
+    };
+    use chrono::{
+        TimeZone,
+        Utc,
+    };
+    use cms::{
+        cert::CertificateChoices,
+        signed_data::{
+            CertificateSet,
+            SignedData,
+        },
+    };
+    use der::{
+        asn1::{
+            BitString,
+            Int,
+            Null,
+            ObjectIdentifier,
+            OctetString,
+            Uint,
+        },
+        Any,
+        Decode,
+        Encode,
+    };
+    use sequoia_openpgp::{
+        cert::{
+            CertBuilder,
+            CipherSuite,
+        },
+        crypto::{
+            mpi,
+            KeyPair,
+            Signer,
+        },
+        types::HashAlgorithm,
+    };
+    use sha2::{
+        Digest,
+        Sha256,
+    };
+    use x509_cert::{
+        certificate::{
+            TbsCertificate,
+            Version,
+        },
+        name::Name,
+        serial_number::SerialNumber,
+        spki::{
+            AlgorithmIdentifierOwned,
+            SubjectPublicKeyInfoOwned,
+        },
+        time::Validity,
+        Certificate,
+    };
+    use crate::signer::PublicKey;
+    use super::{
+        cms_signature,
+        parse_request,
+        EcdsaSigValue,
+        MessageImprint,
+        RsaPublicKey,
+        TimeStampReq,
+        TimeStampResp,
+        Tsa,
+        TstInfo,
+        ECDSA_WITH_SHA256,
+        ID_CT_TSTINFO,
+        ID_MESSAGE_DIGEST,
+        ID_SHA256,
+        ID_SIGNED_DATA,
+        SHA256_WITH_RSA_ENCRYPTION,
+    };
+
+    const POLICY: &str = "1.3.6.1.4.1.99999.1";
+
+    fn keypair(suite: CipherSuite) -> KeyPair {
+        let (cert, _) = CertBuilder::new().set_cipher_suite(suite).generate().unwrap();
+        return cert
+            .primary_key()
+            .key()
+            .clone()
+            .parts_into_secret()
+            .unwrap()
+            .role_into_unspecified()
+            .into_keypair()
+            .unwrap();
+    }
+
+    /// A self-issued certificate for the key, the TSA only looks at the public key
+    fn certificate(public_key: &PublicKey) -> Certificate {
+        let oid = |o: &str| ObjectIdentifier::new_unwrap(o);
+        let (algorithm, key) = match public_key.mpis() {
+            mpi::PublicKey::RSA { e, n } => (AlgorithmIdentifierOwned {
+                // rsaEncryption
+                oid: oid("1.2.840.113549.1.1.1"),
+                parameters: Some(Any::encode_from(&Null).unwrap()),
+            }, RsaPublicKey {
+                modulus: Uint::new(n.value()).unwrap(),
+                public_exponent: Uint::new(e.value()).unwrap(),
+            }.to_der().unwrap()),
+            mpi::PublicKey::ECDSA { q, .. } => (AlgorithmIdentifierOwned {
+                // id-ecPublicKey on prime256v1
+                oid: oid("1.2.840.10045.2.1"),
+                parameters: Some(Any::encode_from(&oid("1.2.840.10045.3.1.7")).unwrap()),
+            }, q.value().to_vec()),
+            mpi::PublicKey::EdDSA { q, .. } => (AlgorithmIdentifierOwned {
+                // Ed25519, without OpenPGP's 0x40 prefix
+                oid: oid("1.3.101.112"),
+                parameters: None,
+            }, q.value()[1 ..].to_vec()),
+            _ => panic!(),
+        };
+        let signature_algorithm = AlgorithmIdentifierOwned {
+            oid: ECDSA_WITH_SHA256,
+            parameters: None,
+        };
+        let name = Name::from_str("CN=yestary test").unwrap();
+        return Certificate {
+            tbs_certificate: TbsCertificate {
+                version: Version::V3,
+                serial_number: SerialNumber::new(&[1]).unwrap(),
+                signature: signature_algorithm.clone(),
+                issuer: name.clone(),
+                validity: Validity::from_now(Duration::from_secs(3600)).unwrap(),
+                subject: name,
+                subject_public_key_info: SubjectPublicKeyInfoOwned {
+                    algorithm: algorithm,
+                    subject_public_key: BitString::from_bytes(&key).unwrap(),
+                },
+                issuer_unique_id: None,
+                subject_unique_id: None,
+                extensions: None,
+            },
+            signature_algorithm: signature_algorithm,
+            signature: BitString::from_bytes(&[0]).unwrap(),
+        };
+    }
+
+    fn request(imprint: &[u8]) -> TimeStampReq {
+        return parse_request(&TimeStampReq {
+            version: 1,
+            message_imprint: MessageImprint {
+                hash_algorithm: AlgorithmIdentifierOwned {
+                    oid: ID_SHA256,
+                    parameters: None,
+                },
+                hashed_message: OctetString::new(imprint).unwrap(),
+            },
+            req_policy: None,
+            nonce: Some(Int::new(&[0x12, 0x34]).unwrap()),
+            cert_req: true,
+            extensions: None,
+        }.to_der().unwrap()).unwrap();
+    }
+
+    /// Issue a token with the key then decode it independently of the encoder and
+    /// check it as a client would.
+    fn round_trip(suite: CipherSuite) {
+        let mut keypair = keypair(suite);
+        let public_key = keypair.public().clone();
+        let cert = certificate(&public_key);
+        let tsa = Tsa::new(cert.clone(), POLICY, &public_key).unwrap();
+        let imprint = Sha256::digest(b"document");
+        let req = request(&imprint);
+        let time = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let (tst_info, signed_attrs) = tsa.prepare(&req, time).unwrap();
+        let signature = keypair.sign(HashAlgorithm::SHA256, &Tsa::signed_attrs_digest(&signed_attrs)).unwrap();
+        let resp = tsa.response(&req, tst_info, signed_attrs, &signature, &public_key).unwrap();
+
+        // Decode
+        let resp = TimeStampResp::from_der(&resp).unwrap();
+        assert_eq!(resp.status.status, 0);
+        let token = resp.time_stamp_token.unwrap();
+        assert_eq!(token.content_type, ID_SIGNED_DATA);
+        let signed_data = SignedData::from_der(&token.content.to_der().unwrap()).unwrap();
+        assert_eq!(signed_data.encap_content_info.econtent_type, ID_CT_TSTINFO);
+        let econtent = signed_data.encap_content_info.econtent.unwrap();
+        let tst_info = TstInfo::from_der(econtent.value()).unwrap();
+        assert_eq!(tst_info.policy, ObjectIdentifier::new_unwrap(POLICY));
+        assert_eq!(tst_info.message_imprint.hashed_message.as_bytes(), &imprint[..]);
+        assert_eq!(tst_info.gen_time.to_unix_duration().as_secs(), time.timestamp() as u64);
+        assert_eq!(tst_info.nonce, req.nonce);
+        let CertificateSet(certs) = signed_data.certificates.unwrap();
+        assert!(matches!(certs.get(0), Some(CertificateChoices::Certificate(c)) if *c == cert));
+
+        // Signed attributes commit to the TSTInfo
+        let signer_info = signed_data.signer_infos.0.get(0).unwrap();
+        assert_eq!(signer_info.sid, super::SignerIdentifier::IssuerAndSerialNumber(super::IssuerAndSerialNumber {
+            issuer: cert.tbs_certificate.issuer.clone(),
+            serial_number: cert.tbs_certificate.serial_number.clone(),
+        }));
+        let signed_attrs = signer_info.signed_attrs.as_ref().unwrap();
+        let digest_attr = signed_attrs.iter().find(|a| a.oid == ID_MESSAGE_DIGEST).unwrap();
+        let digest = OctetString::from_der(&digest_attr.values.get(0).unwrap().to_der().unwrap()).unwrap();
+        assert_eq!(digest.as_bytes(), &Sha256::digest(econtent.value())[..]);
+
+        // Signature over the signed attributes
+        let signature = signer_info.signature.as_bytes();
+        let signature = match signer_info.signature_algorithm.oid {
+            SHA256_WITH_RSA_ENCRYPTION => mpi::Signature::RSA { s: mpi::MPI::new(signature) },
+            ECDSA_WITH_SHA256 => {
+                let value = EcdsaSigValue::from_der(signature).unwrap();
+                mpi::Signature::ECDSA {
+                    r: mpi::MPI::new(value.r.as_bytes()),
+                    s: mpi::MPI::new(value.s.as_bytes()),
+                }
+            },
+            _ => panic!(),
+        };
+        let signed_attrs_digest = Sha256::digest(signed_attrs.to_der().unwrap());
+        public_key.verify(&signature, HashAlgorithm::SHA256, &signed_attrs_digest).unwrap();
+        assert!(public_key.verify(&signature, HashAlgorithm::SHA256, &Sha256::digest(econtent.value())).is_err());
+    }
+
+    #[test]
+    fn round_trip_rsa() {
+        round_trip(CipherSuite::RSA2k);
+    }
+
+    #[test]
+    fn round_trip_ecdsa() {
+        round_trip(CipherSuite::P256);
+    }
+
+    #[test]
+    fn certificate_for_other_key() {
+        let public_key = keypair(CipherSuite::P256).public().clone();
+        let other = keypair(CipherSuite::P256).public().clone();
+        assert!(Tsa::new(certificate(&other), POLICY, &public_key).is_err());
+    }
+
+    #[test]
+    fn unsupported_key() {
+        let mut keypair = keypair(CipherSuite::Cv25519);
+        let public_key = keypair.public().clone();
+        assert!(Tsa::new(certificate(&public_key), POLICY, &public_key).is_err());
+        let signature = keypair.sign(HashAlgorithm::SHA256, &Sha256::digest(b"attributes")).unwrap();
+        assert!(cms_signature(&signature, &public_key).is_err());
+    }
+}
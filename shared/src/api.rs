@@ -0,0 +1,110 @@
+//! Error responses from the server api.  The code is stable, the message is for
+//! people.
+use std::fmt::Display;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiErrorCode {
+    /// The hash isn't lowercase hex of the right length for its algorithm
+    BadHash,
+    /// The hash algorithm isn't supported
+    BadAlgorithm,
+    /// The nonce is empty, too long, or has characters other than printable
+    /// ascii
+    BadNonce,
+    /// No stamp was issued for the hash
+    NotStamped,
+    /// The seal key isn't allowed to sign now (not yet active, retired, or
+    /// compromised)
+    KeyInactive,
+    /// The card holding the seal can't be reached
+    CardUnavailable,
+    /// The card's pin is blocked after too many wrong attempts
+    PinLocked,
+    /// The notary's clock is behind its last stamp or disagrees with its
+    /// reference time source
+    ClockUnreliable,
+    /// Too few Roughtime servers attested the stamp time
+    RoughtimeUnavailable,
+    SigningFailed,
+    /// The stamp couldn't be recorded in the transparency log, so it wasn't issued
+    LogFailed,
+    Internal,
+}
+
+impl ApiErrorCode {
+    /// The request itself was bad, so retrying it won't help.  Other errors are
+    /// on the server's side and may clear up.
+    pub fn is_client_error(&self) -> bool {
+        match self {
+            ApiErrorCode::BadHash |
+            ApiErrorCode::BadAlgorithm |
+            ApiErrorCode::BadNonce |
+            ApiErrorCode::NotStamped => return true,
+            ApiErrorCode::KeyInactive |
+            ApiErrorCode::CardUnavailable |
+            ApiErrorCode::PinLocked |
+            ApiErrorCode::ClockUnreliable |
+            ApiErrorCode::RoughtimeUnavailable |
+            ApiErrorCode::SigningFailed |
+            ApiErrorCode::LogFailed |
+            ApiErrorCode::Internal => return false,
+        }
+    }
+}
+
+impl Display for ApiErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiErrorCode::BadHash => return write!(f, "Hash isn't valid for the algorithm"),
+            ApiErrorCode::BadAlgorithm => return write!(f, "Unsupported hash algorithm"),
+            ApiErrorCode::BadNonce => return write!(f, "Invalid nonce"),
+            ApiErrorCode::NotStamped => return write!(f, "No stamp was issued for the hash"),
+            ApiErrorCode::KeyInactive => return write!(f, "Seal key can't sign at this time"),
+            ApiErrorCode::CardUnavailable => return write!(f, "Seal card is unavailable"),
+            ApiErrorCode::PinLocked => return write!(f, "Seal card pin is locked"),
+            ApiErrorCode::ClockUnreliable => return write!(f, "Notary clock can't be trusted right now"),
+            ApiErrorCode::RoughtimeUnavailable => return write!(f, "Couldn't get the stamp time attested"),
+            ApiErrorCode::SigningFailed => return write!(f, "Signing failed"),
+            ApiErrorCode::LogFailed => return write!(f, "Failed to record stamp in transparency log"),
+            ApiErrorCode::Internal => return write!(f, "Internal error"),
+        }
+    }
+}
+
+/// The json body of `/api/health`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SerialHealth {
+    pub clock: SerialClockStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SerialClockStatus {
+    /// Stamps can be issued
+    pub ok: bool,
+    pub now: DateTime<Utc>,
+    /// Time of the latest stamp issued
+    pub last_stamp: Option<DateTime<Utc>>,
+    /// When the clock was last compared with the reference time source, if one
+    /// is configured
+    pub reference_checked: Option<DateTime<Utc>>,
+    /// How far ahead of the reference the clock was, in milliseconds
+    pub reference_offset_ms: Option<i64>,
+    /// Why stamps are refused
+    pub problem: Option<String>,
+}
+
+/// The json body of every api error response.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SerialApiError {
+    pub code: ApiErrorCode,
+    pub message: String,
+}
@@ -0,0 +1,58 @@
+//! Blinded stamps, so the notary never sees the document hash.  The client
+//! stamps a commitment, the hash of a random salt followed by the document hash,
+//! and keeps the salt in the stamp file.  Verifiers recompute the commitment from
+//! the document.
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use crate::hash::HashAlgorithm;
+
+/// Salt length in bytes
+pub const SALT_LEN: usize = 32;
+
+/// A new random hex salt.
+pub fn new_salt() -> String {
+    let mut salt = [0u8; SALT_LEN];
+    sequoia_openpgp::crypto::random(&mut salt);
+    return hex::encode(salt);
+}
+
+/// The hex commitment to hex `hash` with hex `salt`: the digest of the salt bytes
+/// followed by the hash bytes, using the algorithm that made `hash`.  `None` if
+/// either isn't hex.
+pub fn commitment(algorithm: HashAlgorithm, salt: &str, hash: &str) -> Option<String> {
+    let mut hasher = algorithm.hasher();
+    hasher.update(&hex::decode(salt).ok()?);
+    hasher.update(&hex::decode(hash).ok()?);
+    return Some(hex::encode(hasher.finalize()));
+}
+
+/// The contents of a blinded `.notary_stamp` file.
+#[derive(Serialize, Deserialize)]
+pub struct SerialBlinded {
+    /// Hex salt
+    pub salt: String,
+    /// The stamp file the notary returned for the commitment
+    pub stamp: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::HashAlgorithm;
+    use super::commitment;
+
+    #[test]
+    fn commitments() {
+        let hash = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+        assert_eq!(
+            commitment(HashAlgorithm::Sha256, &"01".repeat(32), hash).as_deref(),
+            Some("1f0ae026f81700e3bd9b14437907bcb5bcc9c830b8fa896d4c9e8ff39c0b2812")
+        );
+        assert_ne!(
+            commitment(HashAlgorithm::Sha256, &"02".repeat(32), hash),
+            commitment(HashAlgorithm::Sha256, &"01".repeat(32), hash)
+        );
+        assert_eq!(commitment(HashAlgorithm::Sha256, "0g", hash), None);
+    }
+}
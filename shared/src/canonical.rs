@@ -0,0 +1,134 @@
+//! The canonical encoding of signed payloads: JSON Canonicalization Scheme
+//! (RFC 8785).  Keys are sorted by UTF-16 code units, there's no whitespace, and
+//! strings use the shortest escapes, so anyone can reproduce the exact signed
+//! bytes from the payload's values.
+//!
+//! Payloads only contain strings and integers, so numbers are limited to
+//! integers a double can hold exactly.
+use serde::Serialize;
+use serde_json::Value;
+
+/// Largest integer that converts to and from a double without loss
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// Serialize `value` in canonical form.
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    let value = serde_json::to_value(value).map_err(|e| e.to_string())?;
+    let mut out = vec![];
+    write_value(&mut out, &value)?;
+    return Ok(out);
+}
+
+/// Whether `data` is json in canonical form.
+pub fn is_canonical(data: &[u8]) -> bool {
+    let Ok(value) = serde_json::from_slice::<Value>(data) else {
+        return false;
+    };
+    return to_vec(&value).is_ok_and(|c| c == data);
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) -> Result<(), String> {
+    match value {
+        Value::Null => out.extend(b"null"),
+        Value::Bool(b) => out.extend(if *b {
+            &b"true"[..]
+        } else {
+            &b"false"[..]
+        }),
+        Value::Number(n) => {
+            let in_range = match (n.as_u64(), n.as_i64()) {
+                (Some(u), _) => u <= MAX_SAFE_INTEGER,
+                (None, Some(i)) => i.unsigned_abs() <= MAX_SAFE_INTEGER,
+                (None, None) => false,
+            };
+            if !in_range {
+                return Err(format!("Number {} isn't a safe integer", n));
+            }
+            out.extend(n.to_string().as_bytes());
+        },
+        Value::String(s) => write_string(out, s),
+        Value::Array(values) => {
+            out.push(b'[');
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_value(out, v)?;
+            }
+            out.push(b']');
+        },
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push(b'{');
+            for (i, (k, v)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_string(out, k);
+                out.push(b':');
+                write_value(out, v)?;
+            }
+            out.push(b'}');
+        },
+    }
+    return Ok(());
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.push(b'"');
+    for c in s.chars() {
+        match c {
+            '"' => out.extend(b"\\\""),
+            '\\' => out.extend(b"\\\\"),
+            '\u{8}' => out.extend(b"\\b"),
+            '\t' => out.extend(b"\\t"),
+            '\n' => out.extend(b"\\n"),
+            '\u{c}' => out.extend(b"\\f"),
+            '\r' => out.extend(b"\\r"),
+            c if (c as u32) < 0x20 => out.extend(format!("\\u{:04x}", c as u32).as_bytes()),
+            c => out.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    out.push(b'"');
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{
+        is_canonical,
+        to_vec,
+    };
+
+    #[test]
+    fn encoding() {
+        let value = json!({
+            "stamp": "2026-10-17T02:35:24Z",
+            "hash": "aa",
+            "serial": 7,
+            "\u{fb01}": "\u{1}\n\"\\/é",
+            "\u{1f600}": [true, null, -1],
+        });
+        assert_eq!(
+            String::from_utf8(to_vec(&value).unwrap()).unwrap(),
+            "{\"hash\":\"aa\",\"serial\":7,\"stamp\":\"2026-10-17T02:35:24Z\",\"\u{1f600}\":[true,null,-1],\
+             \"\u{fb01}\":\"\\u0001\\n\\\"\\\\/é\"}"
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert!(to_vec(&json!({"serial": 9007199254740991u64})).is_ok());
+        assert!(to_vec(&json!({"serial": 9007199254740992u64})).is_err());
+        assert!(to_vec(&json!({"serial": 1.5})).is_err());
+    }
+
+    #[test]
+    fn canonical() {
+        assert!(is_canonical(br#"{"hash":"aa","stamp":"2026-10-17T02:35:24Z"}"#));
+        assert!(!is_canonical(br#"{"stamp":"2026-10-17T02:35:24Z","hash":"aa"}"#));
+        assert!(!is_canonical(br#"{"hash": "aa","stamp":"2026-10-17T02:35:24Z"}"#));
+        assert!(!is_canonical(br#"{"hash":"\u0061a","stamp":"2026-10-17T02:35:24Z"}"#));
+    }
+}
@@ -0,0 +1,141 @@
+//! Every signed stamp body links to the one the notary signed before it, so a
+//! backdated stamp would contradict the stamps around it.
+use std::fmt::Display;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+use crate::verify::ParsedStamp;
+
+/// The previous signature the notary made.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SerialLink {
+    pub serial: u64,
+    /// Hex sha256 of the previous signed body
+    pub hash: String,
+}
+
+impl SerialLink {
+    pub fn to(serial: u64, body: &[u8]) -> SerialLink {
+        return SerialLink {
+            serial: serial,
+            hash: hex::encode(Sha256::digest(body)),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainError {
+    /// The stamp at this index has no serial, so nothing can link to it
+    NoSerial(usize),
+    /// The stamp at this index doesn't link to the one before it
+    Broken(usize),
+    /// The stamp at this index doesn't have the serial after the one before
+    /// it, so a serial was skipped or reused
+    WrongSerial(usize),
+    /// The stamp at this index was made before the one before it
+    TimeReversed(usize),
+}
+
+impl ChainError {
+    /// The index of the stamp with the problem
+    pub fn index(&self) -> usize {
+        match self {
+            ChainError::NoSerial(i) |
+            ChainError::Broken(i) |
+            ChainError::WrongSerial(i) |
+            ChainError::TimeReversed(i) => return *i,
+        }
+    }
+}
+
+impl Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainError::NoSerial(i) => return write!(f, "Stamp {} has no serial", i),
+            ChainError::Broken(i) => return write!(f, "Stamp {} doesn't link to the stamp before it", i),
+            ChainError::WrongSerial(i) => return write!(f, "Stamp {} doesn't follow the serial before it", i),
+            ChainError::TimeReversed(i) => return write!(f, "Stamp {} is dated before the stamp before it", i),
+        }
+    }
+}
+
+impl std::error::Error for ChainError { }
+
+/// Check that `stamps`, in the order they were signed, each link to the one
+/// before with the next serial, and that their times never decrease.  Stamps
+/// from one batch share a signature and may appear together.  This doesn't
+/// check signatures, see `ParsedStamp::verify`.
+pub fn verify_chain(stamps: &[ParsedStamp]) -> Result<(), ChainError> {
+    for (i, pair) in stamps.windows(2).enumerate() {
+        let (previous, stamp) = (&pair[0], &pair[1]);
+        if stamp.body() == previous.body() {
+            continue;
+        }
+        let Some(serial) = previous.stamp.serial else {
+            return Err(ChainError::NoSerial(i));
+        };
+        if stamp.stamp.previous.as_ref() != Some(&SerialLink::to(serial, previous.body())) {
+            return Err(ChainError::Broken(i + 1));
+        }
+        if stamp.stamp.serial != serial.checked_add(1) {
+            return Err(ChainError::WrongSerial(i + 1));
+        }
+        if stamp.stamp.stamp < previous.stamp.stamp {
+            return Err(ChainError::TimeReversed(i + 1));
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::verify::{
+        parse_stamp,
+        ParsedStamp,
+    };
+    use super::{
+        verify_chain,
+        ChainError,
+    };
+
+    fn chain(files: &[&[u8]]) -> Vec<ParsedStamp> {
+        return files.iter().map(|f| parse_stamp(f).unwrap()).collect();
+    }
+
+    const FIRST: &[u8] = include_bytes!("../fixtures/chain_1.notary_stamp");
+    const SECOND: &[u8] = include_bytes!("../fixtures/chain_2.notary_stamp");
+    const THIRD: &[u8] = include_bytes!("../fixtures/chain_3.notary_stamp");
+
+    #[test]
+    fn continuous() {
+        assert_eq!(verify_chain(&chain(&[FIRST, SECOND, THIRD])), Ok(()));
+        assert_eq!(verify_chain(&chain(&[FIRST, SECOND, SECOND, THIRD])), Ok(()));
+        assert_eq!(verify_chain(&chain(&[FIRST, THIRD])), Err(ChainError::Broken(1)));
+        assert_eq!(verify_chain(&chain(&[SECOND, FIRST])), Err(ChainError::Broken(1)));
+    }
+
+    #[test]
+    fn wrong_serial() {
+        let gap: &[u8] = include_bytes!("../fixtures/chain_gap.notary_stamp");
+        let duplicate: &[u8] = include_bytes!("../fixtures/chain_duplicate.notary_stamp");
+        assert_eq!(verify_chain(&chain(&[FIRST, SECOND, gap])), Err(ChainError::WrongSerial(2)));
+        assert_eq!(verify_chain(&chain(&[FIRST, SECOND, duplicate])), Err(ChainError::WrongSerial(2)));
+    }
+
+    #[test]
+    fn time_reversed() {
+        let backdated: &[u8] = include_bytes!("../fixtures/chain_backdated.notary_stamp");
+        assert_eq!(verify_chain(&chain(&[FIRST, SECOND, backdated])), Err(ChainError::TimeReversed(2)));
+    }
+
+    #[test]
+    fn no_serial() {
+        let unserialed: &[u8] = include_bytes!("../fixtures/single.notary_stamp");
+        assert_eq!(verify_chain(&chain(&[unserialed, FIRST])), Err(ChainError::NoSerial(0)));
+    }
+}
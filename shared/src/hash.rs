@@ -0,0 +1,168 @@
+//! Document hash algorithms.  Stamps record which one was used, so a stamp can
+//! be checked long after the default changes.
+use std::{
+    fmt::Display,
+    io,
+    str::FromStr,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::Digest;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    /// Stamps from before algorithms were recorded are sha256
+    #[default]
+    Sha256,
+    Sha512,
+    Sha3_256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 4] =
+        [HashAlgorithm::Sha256, HashAlgorithm::Sha512, HashAlgorithm::Sha3_256, HashAlgorithm::Blake3];
+
+    /// The name used in stamps and urls
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => return "sha256",
+            HashAlgorithm::Sha512 => return "sha512",
+            HashAlgorithm::Sha3_256 => return "sha3_256",
+            HashAlgorithm::Blake3 => return "blake3",
+        }
+    }
+
+    /// Digest length in bytes
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha512 => return 64,
+            HashAlgorithm::Sha256 | HashAlgorithm::Sha3_256 | HashAlgorithm::Blake3 => return 32,
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => return Hasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Sha512 => return Hasher::Sha512(sha2::Sha512::new()),
+            HashAlgorithm::Sha3_256 => return Hasher::Sha3_256(sha3::Sha3_256::new()),
+            HashAlgorithm::Blake3 => return Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    /// Whether `hash` is a lowercase hex digest of the right length for this
+    /// algorithm.  Stamps are compared by string, so nothing else is accepted.
+    pub fn is_valid_hex(&self, hash: &str) -> bool {
+        return hash.len() == self.digest_len() * 2 && hash.bytes().all(|b| matches!(b, b'0' ..= b'9' | b'a' ..= b'f'));
+    }
+
+    /// What a merkle leaf commits to for a hash stamped in a batch.  Sha256 leaves
+    /// are the bare hex, as they were before algorithms were recorded.  Others are
+    /// prefixed with the algorithm so a receipt can't be relabeled.
+    pub fn batch_leaf(&self, hash: &str) -> String {
+        match self {
+            HashAlgorithm::Sha256 => return hash.to_string(),
+            a => return format!("{}:{}", a.name(), hash),
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str(self.name());
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return HashAlgorithm::ALL
+            .into_iter()
+            .find(|a| a.name() == s)
+            .ok_or_else(|| format!("Unsupported hash algorithm {}", s));
+    }
+}
+
+/// Incremental hashing with any supported algorithm.
+pub enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+    Sha3_256(sha3::Sha3_256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::Sha3_256(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            },
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => return h.finalize().to_vec(),
+            Hasher::Sha512(h) => return h.finalize().to_vec(),
+            Hasher::Sha3_256(h) => return h.finalize().to_vec(),
+            Hasher::Blake3(h) => return h.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+impl io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashAlgorithm;
+
+    #[test]
+    fn known_digests() {
+        for (algorithm, expected) in [
+            (HashAlgorithm::Sha256, "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"),
+            (
+                HashAlgorithm::Sha512,
+                concat!(
+                    "e7c22b994c59d9cf2b48e549b1e24666636045930d3da7c1acb299d1c3b7f931",
+                    "f94aae41edda2c2b207a36e10f8bcb8d45223e54878f5b316e7ce3b6bc019629"
+                ),
+            ),
+            (HashAlgorithm::Sha3_256, "b314e28493eae9dab57ac4f0c6d887bddbbeb810e900d818395ace558e96516d"),
+            (HashAlgorithm::Blake3, "8e4c7c1b99dbfd50e7a95185fead5ee1448fa904a2fdd778eaf5f2dbfd629a99"),
+        ] {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"hello\n");
+            let digest = hex::encode(hasher.finalize());
+            assert_eq!(digest, expected);
+            assert!(algorithm.is_valid_hex(&digest));
+            assert!(!algorithm.is_valid_hex(&digest.to_uppercase()));
+            assert!(!algorithm.is_valid_hex(&digest[1..]));
+            assert!(!algorithm.is_valid_hex(&format!("{}0", digest)));
+        }
+        assert!(!HashAlgorithm::Sha256.is_valid_hex(&"g".repeat(64)));
+    }
+
+    #[test]
+    fn names() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(algorithm.name().parse::<HashAlgorithm>().unwrap(), algorithm);
+            assert_eq!(serde_json::to_value(algorithm).unwrap(), algorithm.name());
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Seal key lifecycle.  Each published cert in the server's `keys_dir` has a
+//! metadata file next to it saying when the key may make stamps.  Seals are
+//! replaced regularly: the new key is activated when the old one is retired.
+//!
+//! If a seal is stolen, a signed compromise declaration is published next to
+//! it too.
+use std::slice;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use sequoia_openpgp::Cert;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use crate::verify::{
+    SignedMessage,
+    VerifyError,
+};
+
+/// Name of the metadata file for the key with (lowercase hex) id `keyid`,
+/// published alongside the cert named `keyid`.
+pub fn meta_filename(keyid: &str) -> String {
+    return format!("{}.meta.json", keyid);
+}
+
+/// Name of the compromise declaration for the key with (lowercase hex) id
+/// `keyid`, published alongside the cert if the key is compromised.
+pub fn compromise_filename(keyid: &str) -> String {
+    return format!("{}.compromise", keyid);
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SerialKeyMeta {
+    /// The key makes no stamps before this time
+    pub activated: DateTime<Utc>,
+    /// The key makes no stamps after this time.  May be in the future, for
+    /// scheduled rotation.
+    #[serde(default)]
+    pub retired: Option<DateTime<Utc>>,
+    /// The seal may have been stolen at this time, so stamps after it can't be
+    /// trusted
+    #[serde(default)]
+    pub compromised: Option<DateTime<Utc>>,
+}
+
+impl SerialKeyMeta {
+    /// Check that the key was allowed to make a stamp at `time`.
+    pub fn check(&self, time: DateTime<Utc>) -> Result<(), VerifyError> {
+        if let Some(compromised) = self.compromised {
+            if time >= compromised {
+                return Err(VerifyError::KeyCompromised(compromised));
+            }
+        }
+        if time < self.activated {
+            return Err(VerifyError::KeyInactive(format!("Not activated until {}", self.activated.to_rfc3339())));
+        }
+        if let Some(retired) = self.retired {
+            if time >= retired {
+                return Err(VerifyError::KeyInactive(format!("Retired at {}", retired.to_rfc3339())));
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> KeyStatus {
+        if self.compromised.is_some() {
+            return KeyStatus::Compromised;
+        }
+        if now < self.activated {
+            return KeyStatus::Pending;
+        }
+        if self.retired.is_some_and(|r| now >= r) {
+            return KeyStatus::Retired;
+        }
+        return KeyStatus::Active;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStatus {
+    /// Not activated yet
+    Pending,
+    Active,
+    Retired,
+    Compromised,
+    /// The key has no lifecycle metadata
+    Unknown,
+}
+
+/// One seal key in the server's key index (`/api/keys`).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SerialKeyIndexEntry {
+    /// Hex fingerprint of the signing key (which may be a subkey of the cert)
+    pub fingerprint: String,
+    /// Lowercase hex key id, the cert's name under `/api/key/`
+    pub key_id: String,
+    /// OpenPGP public key algorithm, like `EdDSA`
+    pub algorithm: String,
+    /// Validity window.  If a compromise was declared its time is included here.
+    pub meta: Option<SerialKeyMeta>,
+    /// When the key was compromised, from the metadata or a compromise
+    /// declaration, even if the key has no metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compromised: Option<DateTime<Utc>>,
+    pub status: KeyStatus,
+    /// Armored public cert
+    pub cert: String,
+}
+
+/// The body of a compromise declaration, which is a signed message like a stamp.
+/// It's signed by the compromised key itself (like an OpenPGP revocation
+/// certificate) so the server can't forge one for a key it doesn't hold, and a
+/// thief can only use the key to disown it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SerialCompromise {
+    /// Hex fingerprint of the compromised key
+    pub key: String,
+    /// The earliest time the key may have been in someone else's hands
+    pub compromised: DateTime<Utc>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl SerialCompromise {
+    /// Stamps made at or after the compromise can't be trusted.  Earlier stamps
+    /// pass, but verifiers should warn that the key was later compromised.
+    pub fn check(&self, time: DateTime<Utc>) -> Result<(), VerifyError> {
+        if time >= self.compromised {
+            return Err(VerifyError::KeyCompromised(self.compromised));
+        }
+        return Ok(());
+    }
+}
+
+/// Read a compromise declaration and check that it was signed by `cert` and is
+/// about one of its keys.
+pub fn parse_compromise(data: &[u8], cert: &Cert) -> Result<SerialCompromise, VerifyError> {
+    let message = SignedMessage::parse(data)?;
+    message.verify(slice::from_ref(cert))?;
+    let compromise: SerialCompromise =
+        serde_json::from_slice(&message.body).map_err(|e| VerifyError::BadBody(e.to_string()))?;
+    if !cert.keys().any(|k| k.fingerprint().to_hex().eq_ignore_ascii_case(&compromise.key)) {
+        return Err(VerifyError::BadBody("Compromise declaration is for a different key".to_string()));
+    }
+    return Ok(compromise);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use chrono::{
+        DateTime,
+        Utc,
+    };
+    use sequoia_openpgp::Cert;
+    use crate::verify::VerifyError;
+    use super::{
+        parse_compromise,
+        SerialKeyMeta,
+    };
+
+    fn t(s: &str) -> DateTime<Utc> {
+        return s.parse().unwrap();
+    }
+
+    #[test]
+    fn window() {
+        let meta = SerialKeyMeta {
+            activated: t("2026-01-01T00:00:00Z"),
+            retired: Some(t("2027-01-01T00:00:00Z")),
+            compromised: None,
+        };
+        assert!(meta.check(t("2026-10-17T02:35:24Z")).is_ok());
+        assert!(matches!(meta.check(t("2025-12-31T23:59:59Z")), Err(VerifyError::KeyInactive(_))));
+        assert!(matches!(meta.check(t("2027-01-01T00:00:00Z")), Err(VerifyError::KeyInactive(_))));
+    }
+
+    #[test]
+    fn compromised() {
+        let meta: SerialKeyMeta =
+            serde_json::from_str(
+                r#"{"activated": "2026-01-01T00:00:00Z", "compromised": "2026-06-01T00:00:00Z"}"#,
+            ).unwrap();
+        assert!(meta.check(t("2026-05-01T00:00:00Z")).is_ok());
+        assert_eq!(meta.check(t("2026-10-17T02:35:24Z")), Err(VerifyError::KeyCompromised(t("2026-06-01T00:00:00Z"))));
+    }
+
+    #[test]
+    fn compromise_declaration() {
+        let seal = Cert::from_str(include_str!("../fixtures/seal.asc")).unwrap();
+        let stamp_time = t("2026-10-17T02:35:24Z");
+        let before = parse_compromise(include_bytes!("../fixtures/before_stamp.compromise"), &seal).unwrap();
+        assert_eq!(before.check(stamp_time), Err(VerifyError::KeyCompromised(t("2026-06-01T00:00:00Z"))));
+        let after = parse_compromise(include_bytes!("../fixtures/after_stamp.compromise"), &seal).unwrap();
+        assert!(after.check(stamp_time).is_ok());
+    }
+
+    #[test]
+    fn forged_compromise_declaration() {
+        let seal = Cert::from_str(include_str!("../fixtures/seal.asc")).unwrap();
+        assert!(
+            matches!(
+                parse_compromise(include_bytes!("../fixtures/forged.compromise"), &seal),
+                Err(VerifyError::UnknownIssuer(_))
+            )
+        );
+    }
+}
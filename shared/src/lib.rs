@@ -6,9 +6,181 @@ use serde::{
     Deserialize,
     Serialize,
 };
+use crate::{
+    blind::SerialBlinded,
+    chain::SerialLink,
+    hash::HashAlgorithm,
+    roughtime::SerialRoughtime,
+};
 
-#[derive(Serialize, Deserialize)]
+pub mod api;
+pub mod blind;
+pub mod canonical;
+pub mod chain;
+pub mod hash;
+pub mod keys;
+pub mod merkle;
+pub mod roughtime;
+pub mod transparency;
+pub mod trust;
+pub mod verify;
+
+/// The newest stamp payload version this code writes and understands.
+pub const STAMP_VERSION: u32 = 1;
+
+fn is_zero(v: &u32) -> bool {
+    return *v == 0;
+}
+
+fn is_false(v: &bool) -> bool {
+    return !*v;
+}
+
+/// The signed body of a stamp.  Version 0 stamps (from before the version was
+/// recorded) only have `algorithm`, `hash` and `stamp`.  Unknown fields are
+/// ignored, so new optional fields don't need a version bump.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SerialStamp {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u32,
+    /// Stamps from before this was recorded are sha256
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
     pub hash: String,
+    /// `hash` is a commitment to the document hash, see `blind`
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub blinded: bool,
+    pub stamp: DateTime<Utc>,
+    /// Increases with every signature the notary makes, so a batch's stamps share
+    /// one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<u64>,
+    /// Identifies the notary instance that issued the stamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notary: Option<String>,
+    /// Hex fingerprint of the signing key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Chosen by the client when requesting the stamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// The signature the notary made before this one, see `chain`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<SerialLink>,
+    /// Roughtime servers' attestations of the stamp time, see `roughtime`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roughtime: Vec<SerialRoughtime>,
+}
+
+/// The signed body when many hashes are stamped with one signature.  The
+/// version, serial, notary, key, previous and roughtime are as in `SerialStamp`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerialBatch {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u32,
+    /// Hex merkle root over the batch's hashes, see `merkle`
+    pub root: String,
+    /// Number of hashes in the batch
+    pub size: u64,
     pub stamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notary: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<SerialLink>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roughtime: Vec<SerialRoughtime>,
+}
+
+/// The merkle leaf for one hash in a batch.  Blinding and the nonce are part of
+/// the leaf so they can't be changed in the receipt.
+pub fn batch_leaf(algorithm: HashAlgorithm, hash: &str, blinded: bool, nonce: Option<&str>) -> merkle::Hash {
+    let mut leaf = algorithm.batch_leaf(hash);
+    if blinded {
+        leaf.insert_str(0, "blinded:");
+    }
+    if let Some(nonce) = nonce {
+        leaf.push('\n');
+        leaf.push_str(nonce);
+    }
+    return merkle::leaf_hash(leaf.as_bytes());
+}
+
+/// The stamp for one hash in a batch: the signed batch plus the path from this
+/// hash to the signed root.
+#[derive(Serialize, Deserialize)]
+pub struct BatchReceipt {
+    #[serde(default)]
+    pub algorithm: HashAlgorithm,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub blinded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub index: u64,
+    /// Hex sibling hashes, leaf end first
+    pub path: Vec<String>,
+    /// Armored signed message with a `SerialBatch` body
+    pub message: String,
+}
+
+impl BatchReceipt {
+    /// Whether `self.hash` is part of the batch (doesn't check the batch
+    /// signature).
+    pub fn included_in(&self, batch: &SerialBatch) -> bool {
+        let Some(path) = merkle::decode_path(&self.path) else {
+            return false;
+        };
+        let Some(root) =
+            merkle::root_from_inclusion_proof(
+                self.index,
+                batch.size,
+                &batch_leaf(self.algorithm, &self.hash, self.blinded, self.nonce.as_deref()),
+                &path,
+            ) else {
+                return false;
+            };
+        return hex::encode(root) == batch.root;
+    }
+}
+
+/// The contents of a `.notary_stamp` file.
+pub enum StampFile {
+    /// An armored signed message with a `SerialStamp` body
+    Single(Vec<u8>),
+    Batch(BatchReceipt),
+    /// A single or batch stamp of a commitment, with the salt (see `blind`)
+    Blinded {
+        salt: String,
+        stamp: Box<StampFile>,
+    },
+}
+
+impl StampFile {
+    pub fn from_bytes(data: &[u8]) -> Result<StampFile, serde_json::Error> {
+        if data.trim_ascii_start().starts_with(b"{") {
+            let value: serde_json::Value = serde_json::from_slice(data)?;
+            if value.get("salt").is_none() {
+                return Ok(StampFile::Batch(serde_json::from_value(value)?));
+            }
+            let blinded: SerialBlinded = serde_json::from_value(value)?;
+            return Ok(StampFile::Blinded {
+                salt: blinded.salt,
+                stamp: Box::new(StampFile::from_bytes(blinded.stamp.as_bytes())?),
+            });
+        }
+        return Ok(StampFile::Single(data.to_vec()));
+    }
+
+    /// The signed message
+    pub fn message(&self) -> &[u8] {
+        match self {
+            StampFile::Single(m) => m,
+            StampFile::Batch(r) => r.message.as_bytes(),
+            StampFile::Blinded { stamp, .. } => stamp.message(),
+        }
+    }
 }
@@ -0,0 +1,349 @@
+//! Merkle trees as in RFC 6962 (certificate transparency): SHA-256, with leaves
+//! and interior nodes domain separated by a prefix byte.
+use sha2::{
+    Digest,
+    Sha256,
+};
+
+pub type Hash = [u8; 32];
+
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut h = Sha256::new();
+    h.update([0u8]);
+    h.update(data);
+    return h.finalize().into();
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut h = Sha256::new();
+    h.update([1u8]);
+    h.update(left);
+    h.update(right);
+    return h.finalize().into();
+}
+
+/// Largest power of 2 less than `n` (`n` > 1)
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    return k;
+}
+
+/// The root hash of a tree with the given leaf hashes.
+pub fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => return Sha256::new().finalize().into(),
+        1 => return leaves[0],
+        n => {
+            let k = split(n);
+            return node_hash(&root(&leaves[..k]), &root(&leaves[k..]));
+        },
+    }
+}
+
+/// The sibling hashes from leaf `index` up to the root, leaf end first.
+pub fn inclusion_proof(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    let n = leaves.len();
+    if n <= 1 {
+        return vec![];
+    }
+    let k = split(n);
+    if index < k {
+        let mut out = inclusion_proof(index, &leaves[..k]);
+        out.push(root(&leaves[k..]));
+        return out;
+    } else {
+        let mut out = inclusion_proof(index - k, &leaves[k..]);
+        out.push(root(&leaves[..k]));
+        return out;
+    }
+}
+
+/// Recompute the root of a tree of `size` leaves from leaf `index`'s hash and
+/// its inclusion proof. Returns `None` if the proof is malformed.
+pub fn root_from_inclusion_proof(index: u64, size: u64, leaf: &Hash, proof: &[Hash]) -> Option<Hash> {
+    if index >= size {
+        return None;
+    }
+    let mut f = index;
+    let mut s = size - 1;
+    let mut r = *leaf;
+    for p in proof {
+        if s == 0 {
+            return None;
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(p, &r);
+            if f & 1 == 0 {
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    if s != 0 {
+        return None;
+    }
+    return Some(r);
+}
+
+fn subproof(m: usize, leaves: &[Hash], whole: bool, out: &mut Vec<Hash>) {
+    let n = leaves.len();
+    if m == n {
+        if !whole {
+            out.push(root(leaves));
+        }
+        return;
+    }
+    let k = split(n);
+    if m <= k {
+        subproof(m, &leaves[..k], whole, out);
+        out.push(root(&leaves[k..]));
+    } else {
+        subproof(m - k, &leaves[k..], false, out);
+        out.push(root(&leaves[..k]));
+    }
+}
+
+/// Proof that the tree of the first `first` leaves is a prefix of the tree of all
+/// `leaves`. `first` must be between 1 and the number of leaves.
+pub fn consistency_proof(first: usize, leaves: &[Hash]) -> Vec<Hash> {
+    let mut out = vec![];
+    if first == 0 || first > leaves.len() {
+        return out;
+    }
+    subproof(first, leaves, true, &mut out);
+    return out;
+}
+
+/// Check a consistency proof between the tree with `first` leaves and root
+/// `first_root`, and the tree with `second` leaves and root `second_root`.
+pub fn verify_consistency(first: u64, second: u64, first_root: &Hash, second_root: &Hash, proof: &[Hash]) -> bool {
+    if first == 0 || first > second {
+        return false;
+    }
+    if first == second {
+        return proof.is_empty() && first_root == second_root;
+    }
+    let mut proof = proof.to_vec();
+    if first.is_power_of_two() {
+        proof.insert(0, *first_root);
+    }
+    let Some((start, proof)) = proof.split_first() else {
+        return false;
+    };
+    let mut f = first - 1;
+    let mut s = second - 1;
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+    let mut fr = *start;
+    let mut sr = *start;
+    for c in proof {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            if f & 1 == 0 {
+                while f & 1 == 0 && f != 0 {
+                    f >>= 1;
+                    s >>= 1;
+                }
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    return &fr == first_root && &sr == second_root && s == 0;
+}
+
+/// Parse hex hashes as used in proofs in json
+pub fn decode_path(path: &[String]) -> Option<Vec<Hash>> {
+    let mut out = vec![];
+    for p in path {
+        out.push(Hash::try_from(hex::decode(p).ok()?).ok()?);
+    }
+    return Some(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        consistency_proof,
+        inclusion_proof,
+        leaf_hash,
+        root,
+        root_from_inclusion_proof,
+        verify_consistency,
+        Hash,
+    };
+
+    // Test vectors from the RFC 6962 reference implementation
+    const LEAVES: [&[u8]; 8] =
+        [
+            b"",
+            b"\x00",
+            b"\x10",
+            b"\x20\x21",
+            b"\x30\x31",
+            b"\x40\x41\x42\x43",
+            b"\x50\x51\x52\x53\x54\x55\x56\x57",
+            b"\x60\x61\x62\x63\x64\x65\x66\x67\x68\x69\x6a\x6b\x6c\x6d\x6e\x6f",
+        ];
+    const ROOTS: [&str; 8] =
+        [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        return LEAVES[.. n].iter().map(|l| leaf_hash(l)).collect();
+    }
+
+    fn root_of(n: usize) -> Hash {
+        return decode(ROOTS[n - 1]);
+    }
+
+    fn decode(h: &str) -> Hash {
+        return hex::decode(h).unwrap().try_into().unwrap();
+    }
+
+    fn decode_all(hs: &[&str]) -> Vec<Hash> {
+        return hs.iter().map(|h| decode(h)).collect();
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(hex::encode(root(&[])), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        for n in 1 ..= 8 {
+            assert_eq!(root(&leaves(n)), root_of(n), "size {}", n);
+        }
+    }
+
+    #[test]
+    fn inclusion_vectors() {
+        let vectors: [(usize, usize, &[&str]); 5] =
+            [
+                (0, 1, &[]),
+                (0, 8, &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ]),
+                (5, 8, &[
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ]),
+                (2, 3, &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"]),
+                (1, 5, &[
+                    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ]),
+            ];
+        for (index, size, expected) in vectors {
+            let leaves = leaves(size);
+            let proof = inclusion_proof(index, &leaves);
+            assert_eq!(proof, decode_all(expected), "leaf {} of {}", index, size);
+            assert_eq!(
+                root_from_inclusion_proof(index as u64, size as u64, &leaves[index], &proof),
+                Some(root_of(size))
+            );
+        }
+    }
+
+    #[test]
+    fn inclusion_all() {
+        for size in 1 ..= 8 {
+            let leaves = leaves(size);
+            for index in 0 .. size {
+                let proof = inclusion_proof(index, &leaves);
+                let leaf = &leaves[index];
+                assert_eq!(root_from_inclusion_proof(index as u64, size as u64, leaf, &proof), Some(root_of(size)));
+                assert_eq!(root_from_inclusion_proof(size as u64, size as u64, leaf, &proof), None);
+                if size > 1 {
+                    assert_ne!(
+                        root_from_inclusion_proof(((index + 1) % size) as u64, size as u64, leaf, &proof),
+                        Some(root_of(size))
+                    );
+                    assert_eq!(root_from_inclusion_proof(index as u64, size as u64, leaf, &proof[1 ..]), None);
+                }
+                let mut longer = proof.clone();
+                longer.push([0; 32]);
+                assert_eq!(root_from_inclusion_proof(index as u64, size as u64, leaf, &longer), None);
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_vectors() {
+        let vectors: [(usize, usize, &[&str]); 4] =
+            [
+                (1, 1, &[]),
+                (1, 8, &[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ]),
+                (6, 8, &[
+                    "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ]),
+                (2, 5, &[
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ]),
+            ];
+        for (first, second, expected) in vectors {
+            let proof = consistency_proof(first, &leaves(second));
+            assert_eq!(proof, decode_all(expected), "{} to {}", first, second);
+            assert!(verify_consistency(first as u64, second as u64, &root_of(first), &root_of(second), &proof));
+        }
+    }
+
+    #[test]
+    fn consistency_all() {
+        for second in 1 ..= 8 {
+            for first in 1 ..= second {
+                let proof = consistency_proof(first, &leaves(second));
+                let (first, second) = (first as u64, second as u64);
+                let (first_root, second_root) = (root_of(first as usize), root_of(second as usize));
+                assert!(
+                    verify_consistency(first, second, &first_root, &second_root, &proof),
+                    "{} to {}",
+                    first,
+                    second
+                );
+                assert!(!verify_consistency(first, second, &[0; 32], &second_root, &proof));
+                assert!(!verify_consistency(first, second, &first_root, &[0; 32], &proof));
+                if first < second {
+                    assert!(!verify_consistency(second, first, &second_root, &first_root, &proof));
+                    assert!(!verify_consistency(first, second, &first_root, &second_root, &proof[1 ..]));
+                }
+            }
+        }
+        assert!(!verify_consistency(0, 1, &root(&[]), &root_of(1), &[]));
+        assert!(consistency_proof(0, &leaves(8)).is_empty());
+        assert!(consistency_proof(9, &leaves(8)).is_empty());
+    }
+}
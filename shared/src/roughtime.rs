@@ -0,0 +1,449 @@
+//! Roughtime attestations of stamp times.  Before signing, the notary asks
+//! Roughtime servers (<https://roughtime.googlesource.com/roughtime>) to sign a
+//! nonce derived from the stamp body and embeds their responses in it, so
+//! verifiers can check the stamp time against clocks the notary doesn't control.
+use chrono::{
+    DateTime,
+    Duration,
+    TimeZone,
+    Utc,
+};
+use ed25519_dalek::{
+    Signature,
+    VerifyingKey,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::{
+    Digest,
+    Sha512,
+};
+use crate::canonical;
+
+pub const NONCE_LEN: usize = 64;
+
+/// Requests are padded to this size so servers can't be used to amplify traffic
+pub const REQUEST_LEN: usize = 1024;
+
+/// How far a stamp time may be from an attestation, beyond its radius, to allow
+/// for the round trip to the server
+pub const SLACK_SECS: i64 = 10;
+
+const DELEGATION_CONTEXT: &[u8] = b"RoughTime v1 delegation signature--\x00";
+const RESPONSE_CONTEXT: &[u8] = b"RoughTime v1 response signature\x00";
+
+type Tag = [u8; 4];
+
+const SIG: Tag = *b"SIG\x00";
+const NONC: Tag = *b"NONC";
+const PAD: Tag = *b"PAD\xff";
+const SREP: Tag = *b"SREP";
+const CERT: Tag = *b"CERT";
+const INDX: Tag = *b"INDX";
+const PATH: Tag = *b"PATH";
+const ROOT: Tag = *b"ROOT";
+const MIDP: Tag = *b"MIDP";
+const RADI: Tag = *b"RADI";
+const DELE: Tag = *b"DELE";
+const PUBK: Tag = *b"PUBK";
+const MINT: Tag = *b"MINT";
+const MAXT: Tag = *b"MAXT";
+
+/// A Roughtime response embedded in a stamp.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SerialRoughtime {
+    /// Where the notary reached the server, like `roughtime.example.com:2002`
+    pub server: String,
+    /// Hex ed25519 long-term public key of the server
+    pub public_key: String,
+    /// Hex response, signing the nonce from `stamp_nonce`
+    pub response: String,
+}
+
+impl SerialRoughtime {
+    /// Check the response signs `nonce`, returning the time it vouches for.
+    pub fn verify(&self, nonce: &[u8; NONCE_LEN]) -> Result<Attestation, String> {
+        let public_key = hex::decode(&self.public_key).map_err(|e| format!("Public key isn't hex: {}", e))?;
+        let response = hex::decode(&self.response).map_err(|e| format!("Response isn't hex: {}", e))?;
+        return verify_response(&public_key, nonce, &response);
+    }
+}
+
+/// The time a Roughtime server vouched for: the true time was within `radius` of
+/// `midpoint` when it signed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attestation {
+    pub midpoint: DateTime<Utc>,
+    pub radius: Duration,
+}
+
+impl Attestation {
+    /// Whether a stamp made at `time` could have been attested with this.
+    pub fn agrees(&self, time: DateTime<Utc>) -> bool {
+        return (time - self.midpoint).abs() <= self.radius + Duration::seconds(SLACK_SECS);
+    }
+}
+
+/// A server's long-term public key from hex, `None` unless it's 32 bytes.
+pub fn parse_public_key(public_key: &str) -> Option<[u8; 32]> {
+    return <[u8; 32]>::try_from(hex::decode(public_key).ok()?).ok();
+}
+
+/// The nonce the attestations in a signed stamp `body` sign: the SHA-512 of the
+/// canonical body without the attestations, the time, or the chain link.  The
+/// notary only fills those in once the servers have answered, and the attested
+/// time is compared with the stamp time instead.  `None` if `body` isn't a json
+/// object.
+pub fn stamp_nonce(body: &[u8]) -> Option<[u8; NONCE_LEN]> {
+    let mut body: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let fields = body.as_object_mut()?;
+    for field in ["roughtime", "stamp", "serial", "previous"] {
+        fields.remove(field);
+    }
+    return Some(Sha512::digest(canonical::to_vec(&body).ok()?).into());
+}
+
+/// A request for a server to sign `nonce`.
+pub fn request(nonce: &[u8; NONCE_LEN]) -> Vec<u8> {
+    // Header for 2 tags: count, 1 offset, 2 tags
+    let pad = vec![0u8; REQUEST_LEN - 4 * 4 - NONCE_LEN];
+    return encode(&[(NONC, nonce), (PAD, &pad)]);
+}
+
+/// Check that `response` from the server with ed25519 `public_key` signs
+/// `nonce`, returning the time it vouches for.
+pub fn verify_response(public_key: &[u8], nonce: &[u8; NONCE_LEN], response: &[u8]) -> Result<Attestation, String> {
+    let public_key = verifying_key(public_key)?;
+    let response = Message::parse(response)?;
+
+    // The long-term key delegates to an online key for a window of time
+    let cert = Message::parse(response.get(CERT)?)?;
+    let dele_bytes = cert.get(DELE)?;
+    verify_signature(&public_key, DELEGATION_CONTEXT, dele_bytes, cert.get(SIG)?)
+        .map_err(|e| format!("Bad delegation signature: {}", e))?;
+    let dele = Message::parse(dele_bytes)?;
+    let srep_bytes = response.get(SREP)?;
+    verify_signature(&verifying_key(dele.get(PUBK)?)?, RESPONSE_CONTEXT, srep_bytes, response.get(SIG)?)
+        .map_err(|e| format!("Bad response signature: {}", e))?;
+    let srep = Message::parse(srep_bytes)?;
+
+    // The signed root covers a batch of nonces, the path shows this one is in it
+    let mut index = u32::from_le_bytes(response.get_fixed(INDX)?);
+    let path = response.get(PATH)?;
+    if path.len() % 64 != 0 {
+        return Err("Path isn't a list of hashes".to_string());
+    }
+    let mut hash = leaf_hash(nonce);
+    for sibling in path.chunks(64) {
+        hash = match index & 1 {
+            0 => node_hash(&hash, sibling),
+            _ => node_hash(sibling, &hash),
+        };
+        index >>= 1;
+    }
+    if hash != srep.get_fixed::<64>(ROOT)? {
+        return Err("Response doesn't sign the nonce".to_string());
+    }
+    let midpoint = u64::from_le_bytes(srep.get_fixed(MIDP)?);
+    if midpoint < u64::from_le_bytes(dele.get_fixed(MINT)?) || midpoint > u64::from_le_bytes(dele.get_fixed(MAXT)?) {
+        return Err("Response time is outside the delegation window".to_string());
+    }
+    return Ok(Attestation {
+        midpoint: Utc
+            .timestamp_opt((midpoint / 1_000_000) as i64, (midpoint % 1_000_000) as u32 * 1000)
+            .single()
+            .ok_or_else(|| "Response time is out of range".to_string())?,
+        radius: Duration::microseconds(u32::from_le_bytes(srep.get_fixed(RADI)?) as i64),
+    });
+}
+
+fn verifying_key(key: &[u8]) -> Result<VerifyingKey, String> {
+    let key: [u8; 32] = key.try_into().map_err(|_| "Public key isn't 32 bytes".to_string())?;
+    return VerifyingKey::from_bytes(&key).map_err(|e| format!("Bad public key: {}", e));
+}
+
+fn verify_signature(key: &VerifyingKey, context: &[u8], message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let signature = Signature::from_slice(signature).map_err(|e| e.to_string())?;
+    return key.verify_strict(&[context, message].concat(), &signature).map_err(|e| e.to_string());
+}
+
+fn leaf_hash(nonce: &[u8]) -> [u8; 64] {
+    return Sha512::new().chain_update([0]).chain_update(nonce).finalize().into();
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> [u8; 64] {
+    return Sha512::new().chain_update([1]).chain_update(left).chain_update(right).finalize().into();
+}
+
+/// Tags sort by their little endian value
+fn tag_order(tag: &Tag) -> u32 {
+    return u32::from_le_bytes(*tag);
+}
+
+/// Encode a message, `fields` sorted by tag with values a multiple of 4 bytes
+/// long.
+fn encode(fields: &[(Tag, &[u8])]) -> Vec<u8> {
+    let mut out = vec![];
+    out.extend((fields.len() as u32).to_le_bytes());
+    let mut offset = 0u32;
+    for (_, value) in &fields[.. fields.len().saturating_sub(1)] {
+        offset += value.len() as u32;
+        out.extend(offset.to_le_bytes());
+    }
+    for (tag, _) in fields {
+        out.extend(tag);
+    }
+    for (_, value) in fields {
+        out.extend(*value);
+    }
+    return out;
+}
+
+/// A decoded message: a tag count, the offset of every value after the first,
+/// the tags, then the values.
+struct Message<'a> {
+    fields: Vec<(Tag, &'a [u8])>,
+}
+
+impl<'a> Message<'a> {
+    fn parse(data: &'a [u8]) -> Result<Message<'a>, String> {
+        let word =
+            |i: usize| data
+                .get(i * 4 .. i * 4 + 4)
+                .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+                .ok_or_else(|| "Message is truncated".to_string());
+        let count = word(0)? as usize;
+        if count == 0 {
+            return Ok(Message { fields: vec![] });
+        }
+        if count > data.len() / 8 {
+            return Err("Message is truncated".to_string());
+        }
+        let values = &data[count * 8 ..];
+        let mut fields: Vec<(Tag, &[u8])> = vec![];
+        for i in 0 .. count {
+            let start = match i {
+                0 => 0,
+                i => word(i)? as usize,
+            };
+            let end = match i + 1 == count {
+                true => values.len(),
+                false => word(i + 1)? as usize,
+            };
+            if start % 4 != 0 || start > end || end > values.len() {
+                return Err("Message has a bad offset".to_string());
+            }
+            let tag: Tag = data[(count + i) * 4 .. (count + i + 1) * 4].try_into().unwrap();
+            if let Some((previous, _)) = fields.last() {
+                if tag_order(previous) >= tag_order(&tag) {
+                    return Err("Message tags are out of order".to_string());
+                }
+            }
+            fields.push((tag, &values[start .. end]));
+        }
+        return Ok(Message { fields: fields });
+    }
+
+    fn get(&self, tag: Tag) -> Result<&'a [u8], String> {
+        return self
+            .fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| *v)
+            .ok_or_else(|| format!("Message is missing {}", String::from_utf8_lossy(&tag)));
+    }
+
+    fn get_fixed<const N: usize>(&self, tag: Tag) -> Result<[u8; N], String> {
+        return self
+            .get(tag)?
+            .try_into()
+            .map_err(|_| format!("{} isn't {} bytes", String::from_utf8_lossy(&tag), N));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{
+        Duration,
+        TimeZone,
+        Utc,
+    };
+    use ed25519_dalek::{
+        Signer,
+        SigningKey,
+    };
+    use super::{
+        encode,
+        leaf_hash,
+        node_hash,
+        stamp_nonce,
+        verify_response,
+        Message,
+        CERT,
+        DELE,
+        DELEGATION_CONTEXT,
+        INDX,
+        MAXT,
+        MIDP,
+        MINT,
+        NONCE_LEN,
+        NONC,
+        PATH,
+        PUBK,
+        RADI,
+        RESPONSE_CONTEXT,
+        ROOT,
+        SIG,
+        SREP,
+    };
+
+    /// A local stand-in for a Roughtime server, answering a batch of requests at
+    /// a fixed time.
+    struct StandIn {
+        root: SigningKey,
+        online: SigningKey,
+        /// Microseconds
+        mint: u64,
+        maxt: u64,
+    }
+
+    impl StandIn {
+        fn new(seed: u8) -> StandIn {
+            return StandIn {
+                root: SigningKey::from_bytes(&[seed; 32]),
+                online: SigningKey::from_bytes(&[seed + 1; 32]),
+                mint: 0,
+                maxt: u64::MAX,
+            };
+        }
+
+        fn public_key(&self) -> [u8; 32] {
+            return self.root.verifying_key().to_bytes();
+        }
+
+        /// The response to the request at `index` in `requests`, whose length must
+        /// be a power of 2.
+        fn respond(&self, requests: &[Vec<u8>], index: usize, midpoint: u64) -> Vec<u8> {
+            let mut level =
+                requests
+                    .iter()
+                    .map(|r| leaf_hash(Message::parse(r).unwrap().get(NONC).unwrap()))
+                    .collect::<Vec<_>>();
+            let mut path = vec![];
+            let mut i = index;
+            while level.len() > 1 {
+                path.extend(level[i ^ 1]);
+                level = level.chunks(2).map(|pair| node_hash(&pair[0], &pair[1])).collect();
+                i /= 2;
+            }
+            let srep =
+                encode(
+                    &[
+                        (RADI, &1_000_000u32.to_le_bytes()),
+                        (MIDP, &midpoint.to_le_bytes()),
+                        (ROOT, &level[0]),
+                    ],
+                );
+            let dele =
+                encode(
+                    &[
+                        (PUBK, self.online.verifying_key().as_bytes()),
+                        (MINT, &self.mint.to_le_bytes()),
+                        (MAXT, &self.maxt.to_le_bytes()),
+                    ],
+                );
+            let dele_sig = self.root.sign(&[DELEGATION_CONTEXT, &dele].concat()).to_bytes();
+            let cert = encode(&[(SIG, &dele_sig), (DELE, &dele)]);
+            let sig = self.online.sign(&[RESPONSE_CONTEXT, &srep].concat()).to_bytes();
+            return encode(
+                &[
+                    (SIG, &sig),
+                    (PATH, &path),
+                    (SREP, &srep),
+                    (CERT, &cert),
+                    (INDX, &(index as u32).to_le_bytes()),
+                ],
+            );
+        }
+    }
+
+    const MIDPOINT: u64 = 1_792_200_000_123_456;
+
+    fn nonce(seed: u8) -> [u8; NONCE_LEN] {
+        return [seed; NONCE_LEN];
+    }
+
+    #[test]
+    fn request_size() {
+        let request = super::request(&nonce(1));
+        assert_eq!(request.len(), super::REQUEST_LEN);
+        assert_eq!(Message::parse(&request).unwrap().get(NONC).unwrap(), nonce(1));
+    }
+
+    #[test]
+    fn attested() {
+        let server = StandIn::new(1);
+        let response = server.respond(&[super::request(&nonce(1))], 0, MIDPOINT);
+        let attestation = verify_response(&server.public_key(), &nonce(1), &response).unwrap();
+        assert_eq!(attestation.midpoint, Utc.timestamp_opt(1_792_200_000, 123_456_000).unwrap());
+        assert_eq!(attestation.radius, Duration::seconds(1));
+        assert!(attestation.agrees(attestation.midpoint + Duration::seconds(5)));
+        assert!(!attestation.agrees(attestation.midpoint - Duration::hours(1)));
+    }
+
+    #[test]
+    fn batched() {
+        let server = StandIn::new(1);
+        let requests = (0 .. 4).map(|i| super::request(&nonce(i))).collect::<Vec<_>>();
+        for i in 0 .. 4 {
+            let response = server.respond(&requests, i, MIDPOINT);
+            assert!(verify_response(&server.public_key(), &nonce(i as u8), &response).is_ok());
+            assert!(verify_response(&server.public_key(), &nonce((i as u8 + 1) % 4), &response).is_err());
+        }
+    }
+
+    #[test]
+    fn wrong_nonce() {
+        let server = StandIn::new(1);
+        let response = server.respond(&[super::request(&nonce(1))], 0, MIDPOINT);
+        assert_eq!(
+            verify_response(&server.public_key(), &nonce(2), &response),
+            Err("Response doesn't sign the nonce".to_string())
+        );
+    }
+
+    #[test]
+    fn wrong_server() {
+        let server = StandIn::new(1);
+        let response = server.respond(&[super::request(&nonce(1))], 0, MIDPOINT);
+        assert!(verify_response(&StandIn::new(3).public_key(), &nonce(1), &response).is_err());
+    }
+
+    #[test]
+    fn outside_delegation() {
+        let mut server = StandIn::new(1);
+        server.maxt = MIDPOINT - 1;
+        let response = server.respond(&[super::request(&nonce(1))], 0, MIDPOINT);
+        assert_eq!(
+            verify_response(&server.public_key(), &nonce(1), &response),
+            Err("Response time is outside the delegation window".to_string())
+        );
+    }
+
+    #[test]
+    fn nonce_ignores_notary_fields() {
+        assert_eq!(
+            stamp_nonce(br#"{"hash":"00","roughtime":[{"server":"a"}],"stamp":"2026-10-17T00:00:00Z"}"#),
+            stamp_nonce(br#"{"hash":"00","stamp":"2026-10-17T00:00:00Z"}"#)
+        );
+        let linked = br#"{"hash":"00","previous":{"hash":"01","serial":1},"serial":2,"stamp":"2026-10-17T00:00:01Z"}"#;
+        assert_eq!(stamp_nonce(linked), stamp_nonce(br#"{"hash":"00","stamp":"2026-10-17T00:00:00Z"}"#));
+        assert_ne!(
+            stamp_nonce(br#"{"hash":"01","stamp":"2026-10-17T00:00:00Z"}"#),
+            stamp_nonce(br#"{"hash":"00","stamp":"2026-10-17T00:00:00Z"}"#)
+        );
+        assert_eq!(stamp_nonce(b"[]"), None);
+    }
+}
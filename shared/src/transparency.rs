@@ -0,0 +1,206 @@
+//! Types for the append-only log of every issued stamp.
+use chrono::{
+    DateTime,
+    Utc,
+};
+use sequoia_openpgp::Cert;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use crate::{
+    canonical,
+    hash::HashAlgorithm,
+    merkle,
+    verify::{
+        SignedMessage,
+        VerifyError,
+    },
+    SerialStamp,
+};
+
+/// The signed body of a log tree head.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SerialTreeHead {
+    pub tree_size: u64,
+    /// Hex merkle root over the first `tree_size` log entries
+    pub root_hash: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StampFormat {
+    /// `stamp_file` is the contents of a `.notary_stamp` file
+    #[default]
+    NotaryStamp,
+    /// `stamp_file` is a hex DER RFC 3161 TimeStampResp
+    Rfc3161,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogEntry {
+    pub stamp: SerialStamp,
+    /// The stamp as issued
+    pub stamp_file: String,
+    #[serde(default)]
+    pub format: StampFormat,
+}
+
+impl LogEntry {
+    /// The log leaf commits to the stamp, so a stamp file can be checked against
+    /// the log without the rest of the entry.
+    pub fn leaf_hash(&self) -> merkle::Hash {
+        return stamp_leaf_hash(&self.stamp);
+    }
+}
+
+/// The leaf is the stamp's canonical json.  Version 0 sha256 stamps are committed
+/// to without the algorithm, as they were before it was recorded, so older log
+/// entries keep their leaves.
+pub fn stamp_leaf_hash(stamp: &SerialStamp) -> merkle::Hash {
+    #[derive(Serialize)]
+    struct LegacyStamp<'a> {
+        hash: &'a str,
+        stamp: DateTime<Utc>,
+    }
+
+    if stamp.version == 0 && stamp.algorithm == HashAlgorithm::Sha256 {
+        return merkle::leaf_hash(&canonical::to_vec(&LegacyStamp {
+            hash: &stamp.hash,
+            stamp: stamp.stamp,
+        }).unwrap());
+    }
+    return merkle::leaf_hash(&canonical::to_vec(stamp).unwrap());
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    /// Hex sibling hashes, leaf end first
+    pub path: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConsistencyProof {
+    pub first: u64,
+    pub second: u64,
+    /// Hex hashes
+    pub path: Vec<String>,
+}
+
+impl ConsistencyProof {
+    /// Check that the tree `second` (already verified) only appended entries to
+    /// the tree `first`.
+    pub fn verify(&self, first: &SerialTreeHead, second: &SerialTreeHead) -> bool {
+        if first.tree_size != self.first || second.tree_size != self.second {
+            return false;
+        }
+        let Some(path) = merkle::decode_path(&self.path) else {
+            return false;
+        };
+        let Some(roots) = merkle::decode_path(&[first.root_hash.clone(), second.root_hash.clone()]) else {
+            return false;
+        };
+        return merkle::verify_consistency(self.first, self.second, &roots[0], &roots[1], &path);
+    }
+}
+
+/// The earliest stamp the notary issued for a hash, with proof that it's in the
+/// log.  Anyone auditing the log can confirm no entry before it has the hash.
+#[derive(Serialize, Deserialize)]
+pub struct SerialEarliest {
+    pub entry: LogEntry,
+    pub inclusion: InclusionProof,
+    /// Armored signed `SerialTreeHead` for `inclusion.tree_size` leaves
+    pub tree_head: String,
+}
+
+impl SerialEarliest {
+    /// Check that the tree head was signed by a key in `keyring` and the entry is
+    /// in that tree, returning the tree head.
+    pub fn verify(&self, keyring: &[Cert]) -> Result<SerialTreeHead, VerifyError> {
+        let message = SignedMessage::parse(self.tree_head.as_bytes())?;
+        message.verify(keyring)?;
+        let head: SerialTreeHead =
+            serde_json::from_slice(&message.body).map_err(|e| VerifyError::BadBody(e.to_string()))?;
+        if head.tree_size != self.inclusion.tree_size {
+            return Err(VerifyError::NotInLog);
+        }
+        let Some(path) = merkle::decode_path(&self.inclusion.path) else {
+            return Err(VerifyError::NotInLog);
+        };
+        let Some(root) =
+            merkle::root_from_inclusion_proof(
+                self.inclusion.leaf_index,
+                self.inclusion.tree_size,
+                &self.entry.leaf_hash(),
+                &path,
+            ) else {
+                return Err(VerifyError::NotInLog);
+            };
+        if hex::encode(root) != head.root_hash {
+            return Err(VerifyError::NotInLog);
+        }
+        return Ok(head);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        slice,
+        str::FromStr,
+    };
+    use sequoia_openpgp::Cert;
+    use crate::{
+        hash::HashAlgorithm,
+        merkle,
+        verify::VerifyError,
+        SerialStamp,
+    };
+    use super::{
+        stamp_leaf_hash,
+        ConsistencyProof,
+        SerialEarliest,
+        SerialTreeHead,
+    };
+
+    #[test]
+    fn legacy_leaf() {
+        let legacy = r#"{"hash":"aa","stamp":"2026-10-17T02:35:24Z"}"#;
+        let mut stamp: SerialStamp = serde_json::from_str(legacy).unwrap();
+        assert_eq!(stamp_leaf_hash(&stamp), merkle::leaf_hash(legacy.as_bytes()));
+        stamp.algorithm = HashAlgorithm::Blake3;
+        assert_ne!(stamp_leaf_hash(&stamp), merkle::leaf_hash(legacy.as_bytes()));
+    }
+
+    #[test]
+    fn earliest() {
+        let seal = Cert::from_str(include_str!("../fixtures/seal.asc")).unwrap();
+        let mut earliest: SerialEarliest = serde_json::from_str(include_str!("../fixtures/earliest.json")).unwrap();
+        assert_eq!(earliest.verify(slice::from_ref(&seal)).unwrap().tree_size, 3);
+        earliest.inclusion.leaf_index = 0;
+        assert_eq!(earliest.verify(&[seal]).err(), Some(VerifyError::NotInLog));
+    }
+
+    #[test]
+    fn consistency() {
+        let leaves: Vec<merkle::Hash> = (0u8 .. 7).map(|i| merkle::leaf_hash(&[i])).collect();
+        let head = |size: usize| SerialTreeHead {
+            tree_size: size as u64,
+            root_hash: hex::encode(merkle::root(&leaves[.. size])),
+            timestamp: Default::default(),
+        };
+        let mut proof = ConsistencyProof {
+            first: 3,
+            second: 7,
+            path: merkle::consistency_proof(3, &leaves).iter().map(hex::encode).collect(),
+        };
+        assert!(proof.verify(&head(3), &head(7)));
+        assert!(!proof.verify(&head(4), &head(7)));
+        proof.path.pop();
+        assert!(!proof.verify(&head(3), &head(7)));
+    }
+}
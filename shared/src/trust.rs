@@ -0,0 +1,233 @@
+//! Seal keys a verifier trusts without asking the stamping server, so a
+//! compromised server can't serve a forged stamp along with a matching key.
+//! Also the Roughtime servers whose attestations of stamp times it trusts.
+use std::{
+    slice,
+    str::FromStr,
+};
+use chrono::{
+    DateTime,
+    Utc,
+};
+use sequoia_openpgp::{
+    cert::CertParser,
+    parse::Parse,
+    Cert,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use crate::{
+    roughtime,
+    verify::{
+        ParsedStamp,
+        VerifiedStamp,
+        VerifyError,
+    },
+};
+
+/// A trust roots file is a json list of these.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SerialTrustEntry {
+    Seal(SerialTrustRoot),
+    Roughtime(SerialRoughtimeRoot),
+}
+
+/// A trusted seal key
+#[derive(Serialize, Deserialize)]
+pub struct SerialTrustRoot {
+    /// Armored public cert
+    pub cert: String,
+    /// Stamps made before this time aren't accepted
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    /// Stamps made after this time aren't accepted
+    #[serde(default)]
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+/// A trusted Roughtime server
+#[derive(Serialize, Deserialize)]
+pub struct SerialRoughtimeRoot {
+    /// Hex ed25519 long-term public key
+    pub roughtime_key: String,
+}
+
+pub struct TrustRoot {
+    pub cert: Cert,
+    pub not_before: Option<DateTime<Utc>>,
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+impl TrustRoot {
+    pub fn covers(&self, time: DateTime<Utc>) -> bool {
+        if let Some(t) = self.not_before {
+            if time < t {
+                return false;
+            }
+        }
+        if let Some(t) = self.not_after {
+            if time > t {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    /// Verify a stamp from this root's key, made within its window.
+    pub fn verify(&self, stamp: &ParsedStamp) -> Result<VerifiedStamp, VerifyError> {
+        let verified = stamp.verify(slice::from_ref(&self.cert))?;
+        if !self.covers(verified.stamp.stamp) {
+            return Err(VerifyError::OutsideTrustWindow);
+        }
+        return Ok(verified);
+    }
+}
+
+#[derive(Default)]
+pub struct TrustRoots {
+    seals: Vec<TrustRoot>,
+    roughtime_keys: Vec<[u8; 32]>,
+}
+
+impl TrustRoots {
+    /// Read either a json trust roots file (see `SerialTrustEntry`) or a plain
+    /// OpenPGP keyring, whose certs are trusted with no time limits.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut out = TrustRoots::default();
+        if data.trim_ascii_start().starts_with(b"[") {
+            let entries: Vec<SerialTrustEntry> =
+                serde_json::from_slice(data).map_err(|e| format!("Error parsing trust roots json: {}", e))?;
+            for entry in entries {
+                match entry {
+                    SerialTrustEntry::Seal(root) => out.seals.push(TrustRoot {
+                        cert: Cert::from_str(
+                            &root.cert,
+                        ).map_err(|e| format!("Error parsing trust root cert: {}", e))?,
+                        not_before: root.not_before,
+                        not_after: root.not_after,
+                    }),
+                    SerialTrustEntry::Roughtime(root) => out
+                        .roughtime_keys
+                        .push(
+                            roughtime::parse_public_key(
+                                &root.roughtime_key,
+                            ).ok_or_else(|| "Roughtime key must be 64 hex digits".to_string())?,
+                        ),
+                }
+            }
+        } else {
+            for cert in CertParser::from_bytes(data).map_err(|e| format!("Error reading keyring: {}", e))? {
+                out.seals.push(TrustRoot {
+                    cert: cert.map_err(|e| format!("Error parsing keyring cert: {}", e))?,
+                    not_before: None,
+                    not_after: None,
+                });
+            }
+        }
+        return Ok(out);
+    }
+
+    pub fn extend(&mut self, other: TrustRoots) {
+        self.seals.extend(other.seals);
+        self.roughtime_keys.extend(other.roughtime_keys);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.seals.is_empty() && self.roughtime_keys.is_empty();
+    }
+
+    /// Public keys of the trusted Roughtime servers, see
+    /// `ParsedStamp::verify_roughtime`
+    pub fn roughtime_keys(&self) -> &[[u8; 32]] {
+        return &self.roughtime_keys;
+    }
+
+    /// The root for the key that issued `stamp`, if it's pinned.
+    pub fn find(&self, stamp: &ParsedStamp) -> Option<&TrustRoot> {
+        return self.seals.iter().find(|r| stamp.is_issued_by(&r.cert));
+    }
+
+    /// Verify `stamp` against its pinned root, or `None` if its key isn't
+    /// pinned.
+    pub fn verify(&self, stamp: &ParsedStamp) -> Option<Result<VerifiedStamp, VerifyError>> {
+        return Some(self.find(stamp)?.verify(stamp));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{
+        DateTime,
+        Utc,
+    };
+    use crate::{
+        roughtime,
+        verify::{
+            parse_stamp,
+            VerifyError,
+        },
+    };
+    use super::{
+        SerialTrustRoot,
+        TrustRoots,
+    };
+
+    fn roots(not_before: Option<&str>, not_after: Option<&str>) -> TrustRoots {
+        let parse_time = |t: &str| t.parse::<DateTime<Utc>>().unwrap();
+        return TrustRoots::from_bytes(&serde_json::to_vec(&vec![SerialTrustRoot {
+            cert: include_str!("../fixtures/seal.asc").to_string(),
+            not_before: not_before.map(parse_time),
+            not_after: not_after.map(parse_time),
+        }]).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn pinned() {
+        let stamp = parse_stamp(include_bytes!("../fixtures/single.notary_stamp")).unwrap();
+        assert!(roots(Some("2026-01-01T00:00:00Z"), None).verify(&stamp).unwrap().is_ok());
+    }
+
+    #[test]
+    fn keyring() {
+        let stamp = parse_stamp(include_bytes!("../fixtures/single.notary_stamp")).unwrap();
+        let roots = TrustRoots::from_bytes(include_bytes!("../fixtures/seal.asc")).unwrap();
+        assert!(roots.verify(&stamp).unwrap().is_ok());
+    }
+
+    #[test]
+    fn outside_window() {
+        let stamp = parse_stamp(include_bytes!("../fixtures/single.notary_stamp")).unwrap();
+        assert_eq!(
+            roots(None, Some("2026-01-01T00:00:00Z")).verify(&stamp).unwrap().err(),
+            Some(VerifyError::OutsideTrustWindow)
+        );
+    }
+
+    #[test]
+    fn roughtime_keys() {
+        let key = "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+        let roots =
+            TrustRoots::from_bytes(
+                &serde_json::to_vec(
+                    &serde_json::json!(
+                        [{ "cert": include_str!("../fixtures/seal.asc") }, { "roughtime_key": key }]
+                    ),
+                ).unwrap(),
+            ).unwrap();
+        assert_eq!(roots.roughtime_keys(), &[roughtime::parse_public_key(key).unwrap()]);
+        let stamp = parse_stamp(include_bytes!("../fixtures/roughtime.notary_stamp")).unwrap();
+        assert!(roots.verify(&stamp).unwrap().is_ok());
+        assert_eq!(stamp.verify_roughtime(roots.roughtime_keys()).unwrap().len(), 1);
+        assert!(TrustRoots::from_bytes(br#"[{"roughtime_key": "00"}]"#).is_err());
+    }
+
+    #[test]
+    fn not_pinned() {
+        let stamp = parse_stamp(include_bytes!("../fixtures/single.notary_stamp")).unwrap();
+        let roots = TrustRoots::from_bytes(include_bytes!("../fixtures/other.asc")).unwrap();
+        assert!(roots.verify(&stamp).is_none());
+    }
+}
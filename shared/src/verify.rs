@@ -0,0 +1,578 @@
+//! Stamp verification, shared by the web app, cli, and server so every client
+//! checks stamps the same way.
+use std::fmt::Display;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use sequoia_openpgp::{
+    packet::Signature,
+    parse::Parse,
+    policy::{
+        HashAlgoSecurity,
+        Policy,
+        StandardPolicy,
+    },
+    Cert,
+    Fingerprint,
+    KeyHandle,
+    KeyID,
+    Message,
+    Packet,
+};
+use crate::{
+    blind,
+    canonical,
+    roughtime,
+    SerialBatch,
+    SerialStamp,
+    StampFile,
+    STAMP_VERSION,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// The stamp file isn't a signed message or batch receipt
+    BadStampFile(String),
+    /// The signed message is malformed or isn't a signed literal
+    BadMessage(String),
+    /// The signed body isn't a stamp
+    BadBody(String),
+    /// The signed body isn't in canonical form (see `canonical`)
+    NotCanonical,
+    /// The stamp is in a newer format than this verifier understands
+    UnsupportedVersion(u32),
+    /// The stamp is blinded but the file has no salt, or the reverse
+    BadBlinding,
+    /// No key in the keyring issued the stamp
+    UnknownIssuer(KeyID),
+    /// The issuing key wasn't valid (expired, rejected by policy) when the
+    /// signature was made
+    KeyNotValid(String),
+    /// The issuing key isn't marked for signing
+    NotSigningKey,
+    BadSignature,
+    /// The stamp is from a batch but its hash isn't part of the signed batch
+    NotInBatch,
+    /// The log entry isn't part of the signed log tree
+    NotInLog,
+    /// A Roughtime attestation in the stamp is malformed or doesn't sign the
+    /// stamp
+    BadRoughtime(String),
+    /// A Roughtime server vouched for a different time than the stamp's
+    RoughtimeDisagrees {
+        server: String,
+        midpoint: DateTime<Utc>,
+    },
+    /// The stamp was made outside the time window its pinned key is trusted for
+    OutsideTrustWindow,
+    /// The stamp was made while the issuing key wasn't active (see `keys`)
+    KeyInactive(String),
+    /// The stamp was made after the issuing key was compromised
+    KeyCompromised(DateTime<Utc>),
+    /// The stamp is for a different document
+    HashMismatch {
+        document: String,
+        stamp: String,
+    },
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::BadStampFile(e) => return write!(f, "Error parsing stamp file: {}", e),
+            VerifyError::BadMessage(e) => return write!(f, "Bad signed message: {}", e),
+            VerifyError::BadBody(e) => return write!(f, "Couldn't parse stamp body: {}", e),
+            VerifyError::NotCanonical => return write!(f, "Stamp body isn't canonical json"),
+            VerifyError::UnsupportedVersion(v) => return write!(
+                f,
+                "Stamp format version {} is newer than this verifier supports ({})",
+                v,
+                STAMP_VERSION
+            ),
+            VerifyError::BadBlinding => return write!(
+                f,
+                "Stamp file's salt doesn't agree with whether the stamp is blinded"
+            ),
+            VerifyError::UnknownIssuer(k) => return write!(f, "Stamp issued by unknown key {}", k.to_hex()),
+            VerifyError::KeyNotValid(e) => return write!(f, "Issuing key wasn't valid at signature time: {}", e),
+            VerifyError::NotSigningKey => return write!(f, "Issuing key isn't a signing key"),
+            VerifyError::BadSignature => return write!(f, "Stamp signature is invalid"),
+            VerifyError::NotInBatch => return write!(f, "Stamp's hash isn't part of the signed batch"),
+            VerifyError::NotInLog => return write!(f, "Log entry isn't part of the signed log tree"),
+            VerifyError::BadRoughtime(e) => return write!(f, "Bad Roughtime attestation: {}", e),
+            VerifyError::RoughtimeDisagrees { server, midpoint } => return write!(
+                f,
+                "Roughtime server {} attested {}, which doesn't agree with the stamp time",
+                server,
+                midpoint.to_rfc3339()
+            ),
+            VerifyError::OutsideTrustWindow => return write!(
+                f,
+                "Stamp was made outside the period its pinned key is trusted for"
+            ),
+            VerifyError::KeyInactive(e) => return write!(f, "Issuing key wasn't active when the stamp was made: {}", e),
+            VerifyError::KeyCompromised(t) => return write!(
+                f,
+                "Stamp was made after the issuing key was compromised at {}",
+                t.to_rfc3339()
+            ),
+            VerifyError::HashMismatch { document, stamp } => return write!(
+                f,
+                "Document hash {} doesn't match stamp hash {}",
+                document,
+                stamp
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError { }
+
+/// A signed literal message, the format of stamps and other signed statements
+/// from the notary, whose signature hasn't been checked yet.
+pub(crate) struct SignedMessage {
+    pub(crate) issuer: KeyID,
+    pub(crate) body: Vec<u8>,
+    signature: Signature,
+}
+
+impl SignedMessage {
+    pub(crate) fn parse(message_bytes: &[u8]) -> Result<SignedMessage, VerifyError> {
+        let message = Message::from_bytes(message_bytes).map_err(|e| VerifyError::BadMessage(e.to_string()))?;
+        let mut children = message.children();
+        let Some(Packet::OnePassSig(sign0)) = children.next() else {
+            return Err(VerifyError::BadMessage("Missing signature packet 1".to_string()));
+        };
+        let Some(Packet::Literal(body)) = children.next() else {
+            return Err(VerifyError::BadMessage("Missing literal".to_string()));
+        };
+        let Some(Packet::Signature(sign1)) = children.next() else {
+            return Err(VerifyError::BadMessage("Missing signature packet 2".to_string()));
+        };
+        return Ok(SignedMessage {
+            issuer: sign0.issuer().clone(),
+            body: body.body().to_vec(),
+            signature: sign1.clone(),
+        });
+    }
+
+    fn issuers(&self) -> Vec<KeyHandle> {
+        // Prefer the issuer fingerprint subpacket, fall back to the key id
+        let mut issuers = self.signature.get_issuers();
+        issuers.push(KeyHandle::from(self.issuer.clone()));
+        return issuers;
+    }
+
+    pub(crate) fn is_issued_by(&self, cert: &Cert) -> bool {
+        return cert.keys().key_handles(self.issuers().iter()).next().is_some();
+    }
+
+    /// The issuer may be the primary key or a subkey, but it must be a signing
+    /// key valid under the standard policy when the signature was made.
+    pub(crate) fn verify(&self, keyring: &[Cert]) -> Result<(), VerifyError> {
+        let issuers = self.issuers();
+        let Some(cert) = keyring.iter().find(|c| self.is_issued_by(c)) else {
+            return Err(VerifyError::UnknownIssuer(self.issuer.clone()));
+        };
+        let policy = StandardPolicy::new();
+        let key =
+            cert
+                .keys()
+                .with_policy(&policy, self.signature.signature_creation_time())
+                .key_handles(issuers.iter())
+                .next()
+                .ok_or_else(|| VerifyError::KeyNotValid("Not bound or rejected by policy".to_string()))?;
+        key.alive().map_err(|e| VerifyError::KeyNotValid(e.to_string()))?;
+        if !key.for_signing() {
+            return Err(VerifyError::NotSigningKey);
+        }
+        let signature = self.signature.clone();
+        if signature.verify_message(key.key(), &self.body).is_err() {
+            return Err(VerifyError::BadSignature);
+        }
+        policy
+            .signature(&signature, HashAlgoSecurity::CollisionResistance)
+            .map_err(|e| VerifyError::KeyNotValid(e.to_string()))?;
+        return Ok(());
+    }
+}
+
+/// A decoded stamp file whose signature hasn't been checked yet.
+pub struct ParsedStamp {
+    pub stamp: SerialStamp,
+    /// The salt from the stamp file, if the stamp is blinded
+    pub salt: Option<String>,
+    /// The key id of the key that made the signature
+    pub issuer: KeyID,
+    message: SignedMessage,
+    in_batch: Option<bool>,
+}
+
+pub struct VerifiedStamp {
+    pub stamp: SerialStamp,
+    pub salt: Option<String>,
+    pub issuer: KeyID,
+    /// The stamp was part of a batch
+    pub batched: bool,
+}
+
+pub fn parse_stamp(stamp_bytes: &[u8]) -> Result<ParsedStamp, VerifyError> {
+    let stamp_file = StampFile::from_bytes(stamp_bytes).map_err(|e| VerifyError::BadStampFile(e.to_string()))?;
+    let message = SignedMessage::parse(stamp_file.message())?;
+    if !canonical::is_canonical(&message.body) {
+        return Err(VerifyError::NotCanonical);
+    }
+    let (salt, stamp_file) = match stamp_file {
+        StampFile::Blinded { salt, stamp } => (Some(salt), *stamp),
+        f => (None, f),
+    };
+    let (stamp, in_batch) = match &stamp_file {
+        StampFile::Blinded { .. } => {
+            return Err(VerifyError::BadStampFile("Blinded stamp contains a blinded stamp".to_string()));
+        },
+        StampFile::Single(_) => {
+            (
+                serde_json::from_slice::<SerialStamp>(&message.body).map_err(|e| VerifyError::BadBody(e.to_string()))?,
+                None,
+            )
+        },
+        StampFile::Batch(receipt) => {
+            let batch: SerialBatch =
+                serde_json::from_slice(&message.body).map_err(|e| VerifyError::BadBody(e.to_string()))?;
+            (SerialStamp {
+                version: batch.version,
+                algorithm: receipt.algorithm,
+                hash: receipt.hash.clone(),
+                blinded: receipt.blinded,
+                stamp: batch.stamp,
+                serial: batch.serial,
+                notary: batch.notary.clone(),
+                key: batch.key.clone(),
+                nonce: receipt.nonce.clone(),
+                previous: batch.previous.clone(),
+                roughtime: batch.roughtime.clone(),
+            }, Some(receipt.included_in(&batch)))
+        },
+    };
+    if stamp.version > STAMP_VERSION {
+        return Err(VerifyError::UnsupportedVersion(stamp.version));
+    }
+    if stamp.blinded != salt.is_some() {
+        return Err(VerifyError::BadBlinding);
+    }
+    if let Some(key) = &stamp.key {
+        let fingerprint = Fingerprint::from_hex(key).map_err(|e| VerifyError::BadBody(e.to_string()))?;
+        if KeyID::from(&fingerprint) != message.issuer {
+            return Err(VerifyError::BadBody("Stamp names a different key than the one that signed it".to_string()));
+        }
+    }
+    return Ok(ParsedStamp {
+        stamp: stamp,
+        salt: salt,
+        issuer: message.issuer.clone(),
+        message: message,
+        in_batch: in_batch,
+    });
+}
+
+impl VerifiedStamp {
+    /// Whether the stamp is for the document with hex hash `document_hash`,
+    /// recomputing the commitment if the stamp is blinded.
+    pub fn covers(&self, document_hash: &str) -> bool {
+        match &self.salt {
+            Some(salt) => {
+                return blind::commitment(self.stamp.algorithm, salt, document_hash).as_deref() ==
+                    Some(&self.stamp.hash);
+            },
+            None => return self.stamp.hash == document_hash,
+        }
+    }
+}
+
+impl ParsedStamp {
+    /// The signed body, shared by every stamp in a batch
+    pub fn body(&self) -> &[u8] {
+        return &self.message.body;
+    }
+
+    /// The stamp claims to be signed by `cert`'s primary key or one of its
+    /// subkeys.
+    pub fn is_issued_by(&self, cert: &Cert) -> bool {
+        return self.message.is_issued_by(cert);
+    }
+
+    /// Check the signature against the keys in `keyring`. The issuer may be the
+    /// primary key or a subkey, but it must be a signing key valid under the
+    /// standard policy when the signature was made. This doesn't check which
+    /// document the stamp is for, see `verify_stamp`, or Roughtime attestations,
+    /// see `verify_roughtime`.
+    pub fn verify(&self, keyring: &[Cert]) -> Result<VerifiedStamp, VerifyError> {
+        self.message.verify(keyring)?;
+        if self.in_batch == Some(false) {
+            return Err(VerifyError::NotInBatch);
+        }
+        return Ok(VerifiedStamp {
+            stamp: self.stamp.clone(),
+            salt: self.salt.clone(),
+            issuer: self.issuer.clone(),
+            batched: self.in_batch.is_some(),
+        });
+    }
+
+    /// Check the Roughtime attestations by servers whose ed25519 public keys are
+    /// in `trusted`: each must sign the stamp and agree with its time.  The stamp
+    /// names each server's key itself, so attestations by other servers prove
+    /// nothing and are skipped.  Only meaningful once the stamp is verified.
+    pub fn verify_roughtime(&self, trusted: &[[u8; 32]]) -> Result<Vec<AttestedTime>, VerifyError> {
+        let nonce =
+            roughtime::stamp_nonce(
+                self.body(),
+            ).ok_or_else(|| VerifyError::BadBody("Stamp body isn't an object".to_string()))?;
+        let mut out = vec![];
+        for attestation in &self.stamp.roughtime {
+            let Some(key) = roughtime::parse_public_key(&attestation.public_key) else {
+                continue;
+            };
+            if !trusted.contains(&key) {
+                continue;
+            }
+            let verified =
+                attestation
+                    .verify(&nonce)
+                    .map_err(|e| VerifyError::BadRoughtime(format!("{}: {}", attestation.server, e)))?;
+            if !verified.agrees(self.stamp.stamp) {
+                return Err(VerifyError::RoughtimeDisagrees {
+                    server: attestation.server.clone(),
+                    midpoint: verified.midpoint,
+                });
+            }
+            out.push(AttestedTime {
+                server: attestation.server.clone(),
+                public_key: key,
+                attestation: verified,
+            });
+        }
+        return Ok(out);
+    }
+}
+
+/// A stamp's time as attested by a trusted Roughtime server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AttestedTime {
+    pub server: String,
+    pub public_key: [u8; 32],
+    pub attestation: roughtime::Attestation,
+}
+
+/// Check that `stamp_bytes` (the contents of a stamp file) is a valid stamp
+/// issued by a key in `keyring` for the document with hex hash `document_hash`.
+pub fn verify_stamp(stamp_bytes: &[u8], document_hash: &str, keyring: &[Cert]) -> Result<VerifiedStamp, VerifyError> {
+    let verified = parse_stamp(stamp_bytes)?.verify(keyring)?;
+    if !verified.covers(document_hash) {
+        return Err(VerifyError::HashMismatch {
+            document: document_hash.to_string(),
+            stamp: verified.stamp.hash,
+        });
+    }
+    return Ok(verified);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use sequoia_openpgp::Cert;
+    use crate::roughtime;
+    use super::{
+        parse_stamp,
+        verify_stamp,
+        VerifyError,
+    };
+
+    const DOCUMENT_HASH: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    fn seal() -> Cert {
+        return Cert::from_str(include_str!("../fixtures/seal.asc")).unwrap();
+    }
+
+    fn other() -> Cert {
+        return Cert::from_str(include_str!("../fixtures/other.asc")).unwrap();
+    }
+
+    #[test]
+    fn single() {
+        let verified =
+            verify_stamp(include_bytes!("../fixtures/single.notary_stamp"), DOCUMENT_HASH, &[other(), seal()]).unwrap();
+        assert_eq!(verified.stamp.hash, DOCUMENT_HASH);
+        assert!(!verified.batched);
+    }
+
+    #[test]
+    fn subkey() {
+        let subkey_seal = Cert::from_str(include_str!("../fixtures/subkey_seal.asc")).unwrap();
+        let verified =
+            verify_stamp(include_bytes!("../fixtures/subkey.notary_stamp"), DOCUMENT_HASH, &[subkey_seal]).unwrap();
+        assert_eq!(verified.stamp.hash, DOCUMENT_HASH);
+    }
+
+    #[test]
+    fn batch() {
+        let verified =
+            verify_stamp(include_bytes!("../fixtures/batch.notary_stamp"), DOCUMENT_HASH, &[seal()]).unwrap();
+        assert_eq!(verified.stamp.hash, DOCUMENT_HASH);
+        assert!(verified.batched);
+    }
+
+    #[test]
+    fn batch_wrong_path() {
+        assert_eq!(
+            verify_stamp(include_bytes!("../fixtures/batch_wrong_index.notary_stamp"), DOCUMENT_HASH, &[seal()]).err(),
+            Some(VerifyError::NotInBatch)
+        );
+    }
+
+    #[test]
+    fn wrong_document() {
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/single.notary_stamp"), &"0".repeat(64), &[seal()]),
+                Err(VerifyError::HashMismatch { .. })
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_issuer() {
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/single.notary_stamp"), DOCUMENT_HASH, &[other()]),
+                Err(VerifyError::UnknownIssuer(_))
+            )
+        );
+    }
+
+    #[test]
+    fn tampered() {
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/tampered.notary_stamp"), DOCUMENT_HASH, &[seal()]),
+                Err(VerifyError::BadSignature)
+            )
+        );
+    }
+
+    #[test]
+    fn v1() {
+        let verified = verify_stamp(include_bytes!("../fixtures/v1.notary_stamp"), DOCUMENT_HASH, &[seal()]).unwrap();
+        assert_eq!(verified.stamp.version, 1);
+        assert_eq!(verified.stamp.serial, Some(7));
+        assert_eq!(verified.stamp.notary.as_deref(), Some("notary.example.com"));
+        assert_eq!(verified.stamp.nonce.as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn v0_defaults() {
+        let verified =
+            verify_stamp(include_bytes!("../fixtures/single.notary_stamp"), DOCUMENT_HASH, &[seal()]).unwrap();
+        assert_eq!(verified.stamp.version, 0);
+        assert_eq!(verified.stamp.serial, None);
+        assert_eq!(verified.stamp.key, None);
+    }
+
+    #[test]
+    fn future_version() {
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/future_version.notary_stamp"), DOCUMENT_HASH, &[seal()]),
+                Err(VerifyError::UnsupportedVersion(2))
+            )
+        );
+    }
+
+    #[test]
+    fn wrong_key() {
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/wrong_key.notary_stamp"), DOCUMENT_HASH, &[seal()]),
+                Err(VerifyError::BadBody(_))
+            )
+        );
+    }
+
+    #[test]
+    fn not_canonical() {
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/not_canonical.notary_stamp"), DOCUMENT_HASH, &[seal()]),
+                Err(VerifyError::NotCanonical)
+            )
+        );
+    }
+
+    #[test]
+    fn blinded() {
+        let verified =
+            verify_stamp(include_bytes!("../fixtures/blinded.notary_stamp"), DOCUMENT_HASH, &[seal()]).unwrap();
+        assert!(verified.stamp.blinded);
+        assert_ne!(verified.stamp.hash, DOCUMENT_HASH);
+        assert!(
+            matches!(
+                verify_stamp(include_bytes!("../fixtures/blinded.notary_stamp"), &"0".repeat(64), &[seal()]),
+                Err(VerifyError::HashMismatch { .. })
+            )
+        );
+    }
+
+    #[test]
+    fn blinded_without_salt() {
+        assert_eq!(
+            verify_stamp(include_bytes!("../fixtures/blinded_unsalted.notary_stamp"), DOCUMENT_HASH, &[seal()]).err(),
+            Some(VerifyError::BadBlinding)
+        );
+    }
+
+    /// The key of the stand-in Roughtime server the fixtures were attested by
+    fn roughtime_key() -> [u8; 32] {
+        return roughtime::parse_public_key("8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c").unwrap();
+    }
+
+    #[test]
+    fn roughtime() {
+        let parsed = parse_stamp(include_bytes!("../fixtures/roughtime.notary_stamp")).unwrap();
+        let verified =
+            verify_stamp(include_bytes!("../fixtures/roughtime.notary_stamp"), DOCUMENT_HASH, &[seal()]).unwrap();
+        assert_eq!(verified.stamp.roughtime.len(), 1);
+        let attested = parsed.verify_roughtime(&[roughtime_key()]).unwrap();
+        assert_eq!(attested.len(), 1);
+        assert_eq!(attested[0].public_key, roughtime_key());
+        assert!(attested[0].attestation.agrees(verified.stamp.stamp));
+    }
+
+    #[test]
+    fn roughtime_untrusted() {
+        let parsed = parse_stamp(include_bytes!("../fixtures/roughtime.notary_stamp")).unwrap();
+        assert_eq!(parsed.verify_roughtime(&[]).unwrap(), vec![]);
+        assert_eq!(parsed.verify_roughtime(&[[2; 32]]).unwrap(), vec![]);
+
+        // Bad attestations under keys nobody trusts don't matter either
+        for stamp in [
+            &include_bytes!("../fixtures/roughtime_backdated.notary_stamp")[..],
+            &include_bytes!("../fixtures/roughtime_wrong_nonce.notary_stamp")[..],
+        ] {
+            verify_stamp(stamp, DOCUMENT_HASH, &[seal()]).unwrap();
+            assert_eq!(parse_stamp(stamp).unwrap().verify_roughtime(&[]).unwrap(), vec![]);
+        }
+    }
+
+    #[test]
+    fn roughtime_backdated() {
+        let parsed = parse_stamp(include_bytes!("../fixtures/roughtime_backdated.notary_stamp")).unwrap();
+        assert!(matches!(parsed.verify_roughtime(&[roughtime_key()]), Err(VerifyError::RoughtimeDisagrees { .. })));
+    }
+
+    #[test]
+    fn roughtime_wrong_nonce() {
+        let parsed = parse_stamp(include_bytes!("../fixtures/roughtime_wrong_nonce.notary_stamp")).unwrap();
+        assert!(matches!(parsed.verify_roughtime(&[roughtime_key()]), Err(VerifyError::BadRoughtime(_))));
+    }
+}
@@ -13,10 +13,6 @@ use chrono::{
     DateTime,
     Utc,
 };
-use sha2::{
-    Sha256,
-    Digest,
-};
 use futures::{
     channel::oneshot,
     StreamExt,
@@ -44,15 +40,28 @@ use rooting::{
     el_from_raw,
 };
 use sequoia_openpgp::{
-    parse::{
-        Parse,
-    },
-    Packet,
-    Message,
     Cert,
+    KeyID,
 };
 use shared::{
-    SerialStamp,
+    api::SerialApiError,
+    blind::{
+        commitment,
+        new_salt,
+        SerialBlinded,
+    },
+    hash::HashAlgorithm,
+    keys::{
+        compromise_filename,
+        meta_filename,
+        SerialKeyMeta,
+    },
+    transparency::{
+        LogEntry,
+        StampFormat,
+    },
+    trust::TrustRoots,
+    verify::parse_stamp,
 };
 use tokio::{
     select,
@@ -69,19 +78,39 @@ use wasm_bindgen::{
 use wasm_bindgen_futures::spawn_local;
 use wasm_streams::ReadableStream;
 use web_sys::{
+    Blob,
+    BlobPropertyBag,
     DragEvent,
+    HtmlAnchorElement,
     HtmlInputElement,
+    HtmlSelectElement,
     File,
+    Url,
 };
+use crate::{
+    matching::{
+        DocumentMatch,
+        FileId,
+        KeySource,
+        Matcher,
+        Stamp,
+        StampMatch,
+    },
+    trust::{
+        FetchedKey,
+        Verifier,
+    },
+};
+
+mod matching;
+mod trust;
 
 const SUFFIX: &'static str = "notary_stamp";
+const ROOTS_SUFFIX: &'static str = "notary_roots";
 
-#[derive(PartialEq)]
-struct Stamp {
-    hash: String,
-    stamp: DateTime<Utc>,
-    verified: bool,
-}
+/// Seal keys trusted without asking the server, see `shared::trust`.  Edit before
+/// building to pin your notary's key.
+const TRUST_ROOTS: &'static [u8] = include_bytes!("../trust_roots.json");
 
 // Wrong/missing in web-sys
 #[wasm_bindgen]
@@ -97,17 +126,11 @@ extern "C" {
 }
 
 struct MyFile {
+    id: FileId,
     name: String,
     state: Prim<Rc<FileState>>,
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum DocumentVerifiedState {
-    Unknown,
-    Yes(DateTime<Utc>),
-    No,
-}
-
 enum FileState {
     Init,
     Inter {
@@ -115,11 +138,14 @@ enum FileState {
     },
     Stamp {
         stamp: Stamp,
+        matched: StampMatch,
     },
     Document {
-        hash: String,
-        verified: DocumentVerifiedState,
+        /// The document's hash with each algorithm
+        hashes: Vec<(HashAlgorithm, String)>,
+        verified: DocumentMatch,
     },
+    Roots,
     Error,
 }
 
@@ -127,12 +153,17 @@ impl PartialEq for FileState {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::Inter { .. }, Self::Inter { .. }) => false,
-            (Self::Stamp { stamp: l_stamp }, Self::Stamp { stamp: r_stamp }) => l_stamp == r_stamp,
             (
-                Self::Document { hash: l_hash, verified: l_verified },
-                Self::Document { hash: r_hash, verified: r_verified },
-            ) => l_hash ==
-                r_hash &&
+                Self::Stamp { stamp: l_stamp, matched: l_matched },
+                Self::Stamp { stamp: r_stamp, matched: r_matched },
+            ) => l_stamp ==
+                r_stamp &&
+                l_matched == r_matched,
+            (
+                Self::Document { hashes: l_hashes, verified: l_verified },
+                Self::Document { hashes: r_hashes, verified: r_verified },
+            ) => l_hashes ==
+                r_hashes &&
                 l_verified == r_verified,
             _ => core::mem::discriminant(self) == core::mem::discriminant(other),
         }
@@ -176,25 +207,206 @@ fn icon2(name1: &str, name2: &str) -> ScopeElement {
     return el("div").classes(&["icon-stack"]).extend(vec![icon(name1), icon(name2)]);
 }
 
+/// Re-read every finished file's state from the matcher, since a new document
+/// or stamp can change the result of files loaded earlier.
+fn refresh_matches(pc: &mut ProcessingContext, files: &lunk::Vec<Rc<MyFile>>, matcher: &Matcher) {
+    for f in files.borrow().value().iter() {
+        let state;
+        if let Some((hashes, verified)) = matcher.document(f.id) {
+            state = FileState::Document {
+                hashes: hashes.to_vec(),
+                verified: verified,
+            };
+        } else if let Some((stamp, matched)) = matcher.stamp(f.id) {
+            state = FileState::Stamp {
+                stamp: stamp.clone(),
+                matched: matched,
+            };
+        } else {
+            continue;
+        }
+        f.state.set(pc, Rc::new(state));
+    }
+}
+
+async fn fetch_key(
+    base_url: &String,
+    public_keys: &Rc<RefCell<HashMap<Vec<u8>, broadcast::Receiver<Result<FetchedKey, StrError>>>>>,
+    issuer: &KeyID,
+) -> Result<FetchedKey, StrError> {
+    let KeyID::V4(keyid) = issuer else {
+        return Err(StrError(format!("Unknown key id type: {:?}", issuer)));
+    };
+    let keyid = keyid.to_vec();
+    let hex_keyid = hex::encode(&keyid);
+    return Ok(public_keys.borrow_mut().entry(keyid).or_insert_with(|| {
+        let (res_set, res) = broadcast::channel(1);
+        let base_url = base_url.clone();
+        spawn_local(async move {
+            res_set.send(async move {
+                let key_str =
+                    reqwasm::http::Request::get(&format!("{}/api/key/{}", base_url, hex_keyid))
+                        .send()
+                        .await
+                        .context("Error during key request")?
+                        .text()
+                        .await
+                        .context("Error reading key response")?;
+                let meta_resp =
+                    reqwasm::http::Request::get(&format!("{}/api/key/{}", base_url, meta_filename(&hex_keyid)))
+                        .send()
+                        .await
+                        .context("Error during key metadata request")?;
+                let meta = match meta_resp.status() {
+                    // The server publishes no lifecycle for the key
+                    404 => None,
+                    _ => Some(
+                        meta_resp.json::<SerialKeyMeta>().await.context("Error reading key metadata response")?,
+                    ),
+                };
+                return Ok(FetchedKey {
+                    cert: Cert::from_str(&key_str).context("Error parsing sq pub key (cert)")?,
+                    meta: meta,
+                });
+            }.await).unwrap_throw();
+        });
+        res
+    }).recv().await.context("Error getting result from key channel")??);
+}
+
+/// The compromise declaration the server publishes for the key, if there is one.
+/// Its signature is checked later, with the key.
+async fn fetch_compromise(base_url: &String, issuer: &KeyID) -> Option<Vec<u8>> {
+    let resp =
+        match reqwasm::http::Request::get(
+            &format!("{}/api/key/{}", base_url, compromise_filename(&issuer.to_hex().to_lowercase())),
+        )
+            .send()
+            .await {
+            Ok(r) => r,
+            Err(e) => {
+                console_dbg!("Error requesting compromise declaration", e.to_string());
+                return None;
+            },
+        };
+    if resp.status() != 200 {
+        return None;
+    }
+    match resp.binary().await {
+        Ok(b) => return Some(b),
+        Err(e) => {
+            console_dbg!("Error reading compromise declaration", e.to_string());
+            return None;
+        },
+    }
+}
+
+/// Stamp a commitment to the document hash and save it with the salt, so the
+/// server never sees the hash.  See `shared::blind`.
+async fn download_blinded(
+    base_url: &str,
+    algorithm: HashAlgorithm,
+    hash: &str,
+    filename: &str,
+) -> Result<(), StrError> {
+    let salt = new_salt();
+    let resp =
+        reqwasm::http::Request::get(
+            &format!(
+                "{}/api/stamp/{}?algorithm={}&blinded=true",
+                base_url,
+                commitment(algorithm, &salt, hash).unwrap_throw(),
+                algorithm
+            ),
+        )
+            .send()
+            .await
+            .context("Error during stamp request")?;
+    if !resp.ok() {
+        let err = resp.json::<SerialApiError>().await.context("Error reading stamp error response")?;
+        return Err(StrError(err.message));
+    }
+    let stamp = resp.text().await.context("Error reading stamp response")?;
+    save_file(filename, &serde_json::to_string(&SerialBlinded {
+        salt: salt,
+        stamp: stamp,
+    }).unwrap_throw());
+    return Ok(());
+}
+
+/// Get every stamp the server issued for any of the document's hashes, and save
+/// them.  Returns how many were found.  Blinded stamps can't be found this way.
+async fn recover_stamps(
+    base_url: &str,
+    hashes: &[(HashAlgorithm, String)],
+    filename: &str,
+) -> Result<usize, StrError> {
+    let mut count = 0;
+    for (algorithm, hash) in hashes {
+        let entries =
+            reqwasm::http::Request::get(&format!("{}/api/stamps/{}?algorithm={}", base_url, hash, algorithm))
+                .send()
+                .await
+                .context("Error during stamp recovery request")?
+                .json::<Vec<LogEntry>>()
+                .await
+                .context("Error reading stamp recovery response")?;
+        for entry in entries {
+            if entry.format != StampFormat::NotaryStamp {
+                continue;
+            }
+            save_file(filename, &entry.stamp_file);
+            count += 1;
+        }
+    }
+    return Ok(count);
+}
+
+/// Have the browser download `data` as a file.
+fn save_file(filename: &str, data: &str) {
+    let mut options = BlobPropertyBag::new();
+    options.type_("application/octet-stream");
+    let blob =
+        Blob::new_with_str_sequence_and_options(&js_sys::Array::of1(&JsValue::from_str(data)), &options)
+            .unwrap_throw();
+    let url = Url::create_object_url_with_blob(&blob).unwrap_throw();
+    let a = document().create_element("a").unwrap_throw().dyn_into::<HtmlAnchorElement>().unwrap_throw();
+    a.set_href(&url);
+    a.set_download(filename);
+    a.click();
+    Url::revoke_object_url(&url).unwrap_throw();
+}
+
 fn process_file(
     pc: &mut ProcessingContext,
     base_url: &String,
-    public_keys: &Rc<RefCell<HashMap<Vec<u8>, broadcast::Receiver<Result<Cert, StrError>>>>>,
+    public_keys: &Rc<RefCell<HashMap<Vec<u8>, broadcast::Receiver<Result<FetchedKey, StrError>>>>>,
+    verifier: &Rc<RefCell<Verifier>>,
+    matcher: &Rc<RefCell<Matcher>>,
     files: &lunk::Vec<Rc<MyFile>>,
     file: File,
 ) {
     match file.name().rsplitn(2, ".").next().unwrap_throw() {
         SUFFIX => {
-            process_stamp_file(pc, &base_url, &public_keys, &files, file);
+            process_stamp_file(pc, &base_url, &public_keys, &verifier, &matcher, &files, file);
+        },
+        ROOTS_SUFFIX => {
+            process_roots_file(pc, &verifier, &matcher, &files, file);
         },
         _ => {
-            process_doc_file(pc, &files, file);
+            process_doc_file(pc, &matcher, &files, file);
         },
     }
 }
 
-fn process_doc_file(pc: &mut ProcessingContext, files: &lunk::Vec<Rc<MyFile>>, file: File) {
+fn process_doc_file(
+    pc: &mut ProcessingContext,
+    matcher: &Rc<RefCell<Matcher>>,
+    files: &lunk::Vec<Rc<MyFile>>,
+    file: File,
+) {
     let out = Rc::new(MyFile {
+        id: matcher.borrow_mut().new_id(),
         name: file.name(),
         state: Prim::new(pc, Rc::new(FileState::Init)),
     });
@@ -204,45 +416,24 @@ fn process_doc_file(pc: &mut ProcessingContext, files: &lunk::Vec<Rc<MyFile>>, f
         let mut stream =
             ReadableStream::into_stream(ReadableStream::from_raw(file.stream().dyn_into().unwrap_throw()));
         let files = files.clone();
+        let matcher = matcher.clone();
         let out = out.clone();
         let body = async move {
-            let mut hash = Sha256::new();
+            let mut hashers = HashAlgorithm::ALL.map(|a| (a, a.hasher()));
             while let Some(Ok(chunk)) = stream.next().await {
                 let bytes = Uint8Array::from(chunk).to_vec();
-                hash.update(&bytes);
+                for (_, hasher) in &mut hashers {
+                    hasher.update(&bytes);
+                }
             }
             eg.event(|pc| {
                 console_dbg!("start process doc event");
-                let mut delete = vec![];
-                let out_state;
-
-                // Finish the row with the result
-                let hash = hex::encode(&hash.finalize());
-                let mut verified = DocumentVerifiedState::Unknown;
-                for (other_i, other) in files.borrow().value().iter().enumerate() {
-                    match other.state.borrow().get().as_ref() {
-                        FileState::Stamp { stamp: other_stamp } if other_stamp.hash == hash => {
-                            delete.push(other_i);
-                            verified = match other_stamp.verified {
-                                true => DocumentVerifiedState::Yes(other_stamp.stamp),
-                                false => DocumentVerifiedState::No,
-                            };
-                        },
-                        _ => { },
-                    }
-                }
-                out_state = FileState::Document {
-                    hash: hash,
-                    verified: verified,
-                };
-                delete.sort();
-                delete.reverse();
-                for i in delete {
-                    files.splice(pc, i, 1, vec![]);
-                }
-
-                // And in any case, finish the current element (may have been deleted above, ok)
-                out.state.set(pc, Rc::new(out_state));
+                let mut matcher = matcher.borrow_mut();
+                matcher.add_document(
+                    out.id,
+                    hashers.into_iter().map(|(a, hasher)| (a, hex::encode(hasher.finalize()))).collect(),
+                );
+                refresh_matches(pc, &files, &matcher);
                 console_dbg!("end of doc file finish event");
             });
         };
@@ -262,11 +453,14 @@ fn process_doc_file(pc: &mut ProcessingContext, files: &lunk::Vec<Rc<MyFile>>, f
 fn process_stamp_file(
     pc: &mut ProcessingContext,
     base_url: &String,
-    public_keys: &Rc<RefCell<HashMap<Vec<u8>, broadcast::Receiver<Result<Cert, StrError>>>>>,
+    public_keys: &Rc<RefCell<HashMap<Vec<u8>, broadcast::Receiver<Result<FetchedKey, StrError>>>>>,
+    verifier: &Rc<RefCell<Verifier>>,
+    matcher: &Rc<RefCell<Matcher>>,
     files: &lunk::Vec<Rc<MyFile>>,
     file: File,
 ) {
     let out = Rc::new(MyFile {
+        id: matcher.borrow_mut().new_id(),
         name: file.name(),
         state: Prim::new(pc, Rc::new(FileState::Init)),
     });
@@ -276,6 +470,8 @@ fn process_stamp_file(
             ReadableStream::into_stream(ReadableStream::from_raw(file.stream().dyn_into().unwrap_throw()));
         let out = out.clone();
         let files = files.clone();
+        let verifier = verifier.clone();
+        let matcher = matcher.clone();
         let public_keys = public_keys.clone();
         let base_url = base_url.clone();
         let eg = pc.eg();
@@ -288,89 +484,29 @@ fn process_stamp_file(
                     while let Some(Ok(chunk)) = stream.next().await {
                         data.extend(Uint8Array::from(chunk).to_vec());
                     }
-                    let signature = Message::from_bytes(&data).context("Error decoding sq signature")?;
-                    let mut children = signature.children();
-                    let Some(Packet::OnePassSig(sign0)) = children.next() else {
-                        return Err(StrError("Missing signature packet 1, bad signature".to_string()));
-                    };
-                    let key: Cert = match sign0.issuer() {
-                        sequoia_openpgp::KeyID::V4(keyid) => {
-                            let keyid = keyid.to_vec();
-                            let hex_keyid = hex::encode(&keyid);
-                            public_keys.borrow_mut().entry(keyid).or_insert_with(|| {
-                                let (res_set, res) = broadcast::channel(1);
-                                spawn_local(async move {
-                                    res_set.send(async move {
-                                        let key_str =
-                                            reqwasm::http::Request::get(
-                                                &format!("{}/api/key/{}", base_url, hex_keyid),
-                                            )
-                                                .send()
-                                                .await
-                                                .context("Error during key request")?
-                                                .text()
-                                                .await
-                                                .context("Error reading key response")?;
-                                        return Ok(
-                                            Cert::from_str(&key_str).context("Error parsing sq pub key (cert)")?,
-                                        );
-                                    }.await).unwrap_throw();
-                                });
-                                res
-                            }).recv().await.context("Error getting result from key channel")??
-                        },
-                        i => return Err(StrError(format!("Unknown key id type: {:?}", i))),
-                    };
-                    let Some(Packet::Literal(body)) = children.next() else {
-                        return Err(StrError("Missing literal, bad signature".to_string()));
-                    };
-                    let Some(Packet:: Signature(mut sign1)) = children.next().cloned() else {
-                        return Err(StrError("Missing signature packet 2, bad signature".to_string()));
+                    let parsed = parse_stamp(&data).context("Error reading stamp")?;
+                    let pinned = verifier.borrow().is_pinned(&parsed);
+                    let fetched = if pinned {
+                        None
+                    } else {
+                        match fetch_key(&base_url, &public_keys, &parsed.issuer).await {
+                            Ok(key) => Some(key),
+                            Err(e) => {
+                                console_dbg!("Couldn't get stamp key from server", out.name, e);
+                                None
+                            },
+                        }
                     };
-                    let stamp: SerialStamp = serde_json::from_slice(body.body()).context("Couldn't parse body")?;
-                    let verified =
-                        sign1.verify_message(key.primary_key().parts_into_public().key(), body.body()).is_ok();
+
+                    let compromise = fetch_compromise(&base_url, &parsed.issuer).await;
 
                     // Finish the row with the result
                     eg.event(|pc| {
                         console_dbg!("start process stamp event");
-                        let mut used_stamp = false;
-                        let mut stamp_index = None;
-                        for (other_i, other) in files.borrow().value().iter().enumerate() {
-                            if other.name == out.name {
-                                stamp_index = Some(other_i);
-                                continue;
-                            }
-                            let hash = match other.state.borrow().get().as_ref() {
-                                FileState::Document { hash: other_hash, .. } => {
-                                    other_hash.clone()
-                                },
-                                _ => {
-                                    continue;
-                                },
-                            };
-                            used_stamp = true;
-                            console_dbg!("setting other state verified", other.name);
-                            other.state.set(pc, Rc::new(FileState::Document {
-                                hash: hash,
-                                verified: match verified {
-                                    true => DocumentVerifiedState::Yes(stamp.stamp),
-                                    false => DocumentVerifiedState::No,
-                                },
-                            }));
-                        }
-                        if used_stamp {
-                            if let Some(i) = stamp_index {
-                                console_dbg!("used stamp, clearing");
-                                files.splice(pc, i, 1, vec![]);
-                            }
-                        } else {
-                            out.state.set(pc, Rc::new(FileState::Stamp { stamp: Stamp {
-                                hash: stamp.hash,
-                                stamp: stamp.stamp,
-                                verified: verified,
-                            } }));
-                        }
+                        let stamp = verifier.borrow_mut().add_stamp(out.id, parsed, fetched, compromise);
+                        let mut matcher = matcher.borrow_mut();
+                        matcher.add_stamp(out.id, stamp);
+                        refresh_matches(pc, &files, &matcher);
                         console_dbg!("end of stamp file finish event");
                     });
                     return Ok(());
@@ -399,10 +535,99 @@ fn process_stamp_file(
     files.push(pc, out);
 }
 
-fn file_el(pc: &mut ProcessingContext, base_url: &String, f: &Rc<MyFile>) -> ScopeElement {
+/// A user-supplied trust roots file or keyring.  Stamps loaded earlier are
+/// re-checked against the new roots.
+fn process_roots_file(
+    pc: &mut ProcessingContext,
+    verifier: &Rc<RefCell<Verifier>>,
+    matcher: &Rc<RefCell<Matcher>>,
+    files: &lunk::Vec<Rc<MyFile>>,
+    file: File,
+) {
+    let out = Rc::new(MyFile {
+        id: matcher.borrow_mut().new_id(),
+        name: file.name(),
+        state: Prim::new(pc, Rc::new(FileState::Init)),
+    });
+    let (cancel_set, cancel) = oneshot::channel::<()>();
+    let eg = pc.eg();
+    spawn_local({
+        let mut stream =
+            ReadableStream::into_stream(ReadableStream::from_raw(file.stream().dyn_into().unwrap_throw()));
+        let files = files.clone();
+        let verifier = verifier.clone();
+        let matcher = matcher.clone();
+        let out = out.clone();
+        let body = async move {
+            let mut data = vec![];
+            while let Some(Ok(chunk)) = stream.next().await {
+                data.extend(Uint8Array::from(chunk).to_vec());
+            }
+            match TrustRoots::from_bytes(&data) {
+                Ok(roots) => {
+                    eg.event(|pc| {
+                        let mut matcher = matcher.borrow_mut();
+                        for (id, stamp) in verifier.borrow_mut().add_roots(roots) {
+                            matcher.add_stamp(id, stamp);
+                        }
+                        out.state.set(pc, Rc::new(FileState::Roots));
+                        refresh_matches(pc, &files, &matcher);
+                    });
+                },
+                Err(e) => {
+                    console_dbg!("Error reading trust roots", out.name, e);
+                    eg.event(|pc| {
+                        out.state.set(pc, Rc::new(FileState::Error));
+                    });
+                },
+            }
+        };
+        async move {
+            select!{
+                _ = cancel =>(),
+                _ = body =>(),
+            }
+        }
+    });
+    out.state.set(pc, Rc::new(FileState::Inter { _future_drop: scope_any(defer::defer(move || {
+        cancel_set.send(()).unwrap_throw();
+    })) }));
+    files.push(pc, out);
+}
+
+fn key_source_el(key: KeySource) -> ScopeElement {
+    let (class, text) = match key {
+        KeySource::Pinned => ("key_pinned", "pinned key"),
+        KeySource::Fetched => ("key_fetched", "key from server"),
+        KeySource::Unknown => ("key_unknown", "unknown key"),
+    };
+    return el("span").classes(&["key_source", class]).text(text);
+}
+
+/// Warns that a stamp's key was compromised, after the stamp was made.
+fn compromised_el(compromised: DateTime<Utc>) -> ScopeElement {
+    return el("span")
+        .classes(&["compromised"])
+        .text(&format!("seal compromised {}", compromised.format("%Y-%m-%d")));
+}
+
+/// Notes a trusted Roughtime server that vouched for the stamp time.
+fn attested_el(server: &str) -> ScopeElement {
+    return el("span").classes(&["attested"]).text(&format!("time attested by {}", server));
+}
+
+fn file_el(
+    pc: &mut ProcessingContext,
+    base_url: &String,
+    algorithm: &Prim<HashAlgorithm>,
+    blind: &Prim<bool>,
+    f: &Rc<MyFile>,
+) -> ScopeElement {
     return el("div").classes(&["file"]).drop(|div| link!((
         _pc = pc;
-        state = f.state.clone();
+        state = f.state.clone(),
+        algorithm = algorithm.clone(),
+        blind = blind.clone();
         f = f.clone(),
         div = div.clone(),
         base_url = base_url.clone(),
@@ -441,39 +666,105 @@ fn file_el(pc: &mut ProcessingContext, base_url: &String, f: &Rc<MyFile>) -> Sco
                     ),
                 );
             },
-            FileState::Stamp { .. } => {
-                div.mut_push(el("div").extend(vec![icon("badge"), el("span").text(&f.name)]));
+            FileState::Stamp { stamp, matched } => {
+                let mut children = vec![match matched {
+                    StampMatch::Pending => icon("badge"),
+                    StampMatch::Matched => icon2("badge", "check"),
+                    StampMatch::Mismatch => icon2("badge", "cross"),
+                }, el("span").text(&f.name), key_source_el(stamp.key)];
+                if let Some(compromised) = stamp.compromised {
+                    children.push(compromised_el(compromised));
+                }
+                children.extend(stamp.attested.iter().map(|server| attested_el(server)));
+                if *matched == StampMatch::Mismatch {
+                    children.push(el("span").classes(&["mismatch"]).text("Doesn't match any document"));
+                }
+                div.mut_push(el("div").extend(children));
             },
-            FileState::Document { hash, verified } => match verified {
-                DocumentVerifiedState::Unknown => {
+            FileState::Document { hashes, verified } => match verified {
+                DocumentMatch::Unstamped => {
                     console_dbg!("verified still unknonwn");
-                    div.mut_push(
+                    let algorithm = *algorithm.borrow().get();
+                    let hash = &hashes.iter().find(|(a, _)| *a == algorithm).unwrap_throw().1;
+                    let filename = format!("{}.{}", f.name, SUFFIX);
+                    let blind = *blind.borrow().get();
+                    let link = if blind {
+                        el("a").attr("href", "#").on("click", {
+                            let base_url = base_url.clone();
+                            let hash = hash.clone();
+                            move |e| {
+                                e.prevent_default();
+                                let base_url = base_url.clone();
+                                let hash = hash.clone();
+                                let filename = filename.clone();
+                                spawn_local(async move {
+                                    if let Err(e) = download_blinded(&base_url, algorithm, &hash, &filename).await {
+                                        console_dbg!("Error getting blinded stamp", filename, e);
+                                    }
+                                });
+                            }
+                        })
+                    } else {
                         el("a")
-                            .attr("href", &format!("{}/api/stamp/{}", base_url, hash))
-                            .attr("download", &format!("{}.{}", f.name, SUFFIX))
-                            .extend(vec![icon("doc"), el("span").text(&f.name)]),
-                    );
+                            .attr("href", &format!("{}/api/stamp/{}?algorithm={}", base_url, hash, algorithm))
+                            .attr("download", &filename)
+                    };
+                    let mut children = vec![link.extend(vec![icon("doc"), el("span").text(&f.name)])];
+
+                    // Recovery looks stamps up by the document hash, which blind stamping keeps
+                    // from the server
+                    if !blind {
+                        children.push(el("button").classes(&["recover"]).text("recover stamp").on("click", {
+                            let base_url = base_url.clone();
+                            let hashes = hashes.clone();
+                            let name = f.name.clone();
+                            move |_| {
+                                let base_url = base_url.clone();
+                                let hashes = hashes.clone();
+                                let name = name.clone();
+                                spawn_local(async move {
+                                    let filename = format!("{}.{}", name, SUFFIX);
+                                    match recover_stamps(&base_url, &hashes, &filename).await {
+                                        Ok(0) => {
+                                            window()
+                                                .alert_with_message(&format!("No stamps were issued for {}", name))
+                                                .unwrap_throw();
+                                        },
+                                        Ok(_) => { },
+                                        Err(e) => {
+                                            console_dbg!("Error recovering stamps", name, e);
+                                        },
+                                    }
+                                });
+                            }
+                        }));
+                    }
+                    div.mut_push(el("div").extend(children));
                 },
-                DocumentVerifiedState::Yes(stamp) => {
+                DocumentMatch::Verified(stamps) => {
                     console_dbg!("verified yes");
-                    div.mut_push(
-                        el(
-                            "div",
-                        ).extend(
-                            vec![
-                                icon2("doc", "check"),
-                                el("span").text(&f.name),
-                                el("time")
-                                    .attr("datetime", &stamp.to_rfc3339())
-                                    .text(&stamp.format("%Y-%m-%d").to_string())
-                            ],
-                        ),
-                    );
+                    let mut children = vec![icon2("doc", "check"), el("span").text(&f.name)];
+                    for stamp in stamps {
+                        children.push(
+                            el("time")
+                                .attr("datetime", &stamp.stamp.to_rfc3339())
+                                .text(&stamp.stamp.format("%Y-%m-%d").to_string()),
+                        );
+                        children.push(key_source_el(stamp.key));
+                        if let Some(compromised) = stamp.compromised {
+                            children.push(compromised_el(compromised));
+                        }
+                        children.extend(stamp.attested.iter().map(|server| attested_el(server)));
+                    }
+                    div.mut_push(el("div").extend(children));
                 },
-                DocumentVerifiedState::No => {
+                DocumentMatch::Failed => {
                     div.mut_push(el("div").extend(vec![icon2("doc", "cross"), el("span").text(&f.name)]));
                 },
             },
+            FileState::Roots => {
+                div.mut_push(el("div").extend(vec![icon("key"), el("span").text(&f.name)]));
+            },
             FileState::Error => {
                 div.mut_push(el("div").extend(vec![icon("error"), el("span").text(&f.name)]));
             },
@@ -488,6 +779,17 @@ fn main() {
         let files: lunk::Vec<_> = lunk::Vec::new(pc, vec![]);
         let base_url = window().location().origin().unwrap_throw();
         let public_keys = Rc::new(RefCell::new(HashMap::new()));
+        let roots = match TrustRoots::from_bytes(TRUST_ROOTS) {
+            Ok(r) => r,
+            Err(e) => {
+                console_dbg!("Error reading built-in trust roots", e);
+                TrustRoots::default()
+            },
+        };
+        let verifier = Rc::new(RefCell::new(Verifier::new(roots)));
+        let matcher = Rc::new(RefCell::new(Matcher::default()));
+        let algorithm = Prim::new(pc, HashAlgorithm::default());
+        let blind = Prim::new(pc, false);
         set_root(
             vec![
                 el("div")
@@ -504,6 +806,8 @@ fn main() {
                         pc = pc;
                         files = files.clone();
                         base_url = base_url.clone(),
+                        algorithm = algorithm.clone(),
+                        blind = blind.clone(),
                         e = e.clone()
                     ) {
                         for c in files.borrow().changes() {
@@ -511,7 +815,7 @@ fn main() {
                                 format!("change files at {} remove {} add {}", c.offset, c.remove, c.add.len())
                             );
                             e.mut_splice(c.offset, c.remove, c.add.iter().map(|f| {
-                                return file_el(pc, &base_url, f);
+                                return file_el(pc, &base_url, &algorithm, &blind, f);
                             }).collect());
                         }
                     })),
@@ -520,6 +824,8 @@ fn main() {
                     ).extend(vec![el("input").attr("type", "file").attr("multiple", "true").on("change", {
                         let base_url = base_url.clone();
                         let public_keys = public_keys.clone();
+                        let verifier = verifier.clone();
+                        let matcher = matcher.clone();
                         let files = files.clone();
                         let eg = pc.eg();
                         move |e| eg.event(|pc| {
@@ -529,7 +835,7 @@ fn main() {
                             let js_files = el.files().unwrap_throw();
                             for i in 0 .. js_files.length() {
                                 let file = js_files.get(i).unwrap_throw();
-                                process_file(pc, &base_url, &public_keys, &files, file);
+                                process_file(pc, &base_url, &public_keys, &verifier, &matcher, &files, file);
                             }
                             console_dbg!("end of new files event 1");
                         })
@@ -538,6 +844,8 @@ fn main() {
                     e.prevent_default();
                 }).on("drop", {
                     let public_keys = public_keys.clone();
+                    let verifier = verifier.clone();
+                    let matcher = matcher.clone();
                     let files = files.clone();
                     let base_url = base_url.clone();
                     let eg = pc.eg();
@@ -548,7 +856,15 @@ fn main() {
                         let datatransfer = e.data_transfer().unwrap_throw();
                         if let Some(js_files) = datatransfer.files() {
                             for i in 0 .. js_files.length() {
-                                process_file(pc, &base_url, &public_keys, &files, js_files.get(i).unwrap_throw());
+                                process_file(
+                                    pc,
+                                    &base_url,
+                                    &public_keys,
+                                    &verifier,
+                                    &matcher,
+                                    &files,
+                                    js_files.get(i).unwrap_throw(),
+                                );
                             }
                         }
                         let items = datatransfer.items();
@@ -556,11 +872,49 @@ fn main() {
                             let Some(file) = items.get(i).unwrap_throw().get_as_file().unwrap_throw() else {
                                 continue;
                             };
-                            process_file(pc, &base_url, &public_keys, &files, file);
+                            process_file(pc, &base_url, &public_keys, &verifier, &matcher, &files, file);
                         }
                         console_dbg!("end of new files event 2");
                     })
                 }),
+                el("label")
+                    .classes(&["algorithm"])
+                    .extend(
+                        vec![
+                            el("span").text("Stamp with"),
+                            el(
+                                "select",
+                            ).extend(
+                                HashAlgorithm::ALL
+                                    .iter()
+                                    .map(|a| el("option").attr("value", a.name()).text(a.name()))
+                                    .collect(),
+                            ).on("change", {
+                                let algorithm = algorithm.clone();
+                                let eg = pc.eg();
+                                move |e| eg.event(|pc| {
+                                    let e = e.target().unwrap_throw();
+                                    let select = e.dyn_ref::<HtmlSelectElement>().unwrap_throw();
+                                    if let Ok(a) = HashAlgorithm::from_str(&select.value()) {
+                                        algorithm.set(pc, a);
+                                    }
+                                })
+                            })
+                        ],
+                    ),
+                el("label")
+                    .classes(&["blind"])
+                    .extend(
+                        vec![el("input").attr("type", "checkbox").on("change", {
+                            let blind = blind.clone();
+                            let eg = pc.eg();
+                            move |e| eg.event(|pc| {
+                                let e = e.target().unwrap_throw();
+                                let input = e.dyn_ref::<HtmlInputElement>().unwrap_throw();
+                                blind.set(pc, input.checked());
+                            })
+                        }), el("span").text("Blind (the notary never sees the document's hash)")],
+                    ),
                 el("div")
                     .classes(&["footer"])
                     .extend(
@@ -0,0 +1,272 @@
+//! Pairs loaded documents with loaded stamps by hash.  Files can arrive in any
+//! order, so the matcher only holds what's been loaded so far and the page
+//! re-reads every file's state from it after each change.
+use std::collections::BTreeMap;
+use chrono::{
+    DateTime,
+    Utc,
+};
+use shared::{
+    blind::commitment,
+    hash::HashAlgorithm,
+};
+
+/// Identifies a dropped file.  Names aren't unique (two `notes.txt` from
+/// different folders) so they're only used for display.
+pub type FileId = usize;
+
+/// Where the key that checked a stamp came from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum KeySource {
+    /// One of the trust roots built into the page or loaded by the user
+    Pinned,
+    /// Downloaded from the server that issued the stamp
+    Fetched,
+    /// No key was found, so the stamp couldn't be checked
+    Unknown,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Stamp {
+    pub algorithm: HashAlgorithm,
+    pub hash: String,
+    /// If the stamp is blinded, `hash` is a commitment made with this salt
+    pub salt: Option<String>,
+    pub stamp: DateTime<Utc>,
+    pub verified: bool,
+    pub key: KeySource,
+    /// A compromise of the key was declared at this time.  If the stamp verified
+    /// it was made earlier, but is still suspect.
+    pub compromised: Option<DateTime<Utc>>,
+    /// Trusted Roughtime servers that attested the stamp time
+    pub attested: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DocumentMatch {
+    /// No loaded stamp has this document's hash.
+    Unstamped,
+    /// Every verified stamp with this document's hash, earliest first.
+    Verified(Vec<Stamp>),
+    /// Stamps with this document's hash were loaded but none verified.
+    Failed,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StampMatch {
+    /// No documents loaded to compare against yet.
+    Pending,
+    /// At least one loaded document has this stamp's hash.
+    Matched,
+    /// Documents are loaded but none has this stamp's hash.
+    Mismatch,
+}
+
+fn stamp_of(hashes: &[(HashAlgorithm, String)], stamp: &Stamp) -> bool {
+    return hashes.iter().any(|(a, h)| *a == stamp.algorithm && match &stamp.salt {
+        Some(salt) => commitment(*a, salt, h).as_deref() == Some(&stamp.hash),
+        None => h == &stamp.hash,
+    });
+}
+
+#[derive(Default)]
+pub struct Matcher {
+    next_id: FileId,
+    /// Each document is hashed with every algorithm, since stamps can use any
+    documents: BTreeMap<FileId, Vec<(HashAlgorithm, String)>>,
+    stamps: BTreeMap<FileId, Stamp>,
+}
+
+impl Matcher {
+    pub fn new_id(&mut self) -> FileId {
+        let id = self.next_id;
+        self.next_id += 1;
+        return id;
+    }
+
+    pub fn add_document(&mut self, id: FileId, hashes: Vec<(HashAlgorithm, String)>) {
+        self.documents.insert(id, hashes.into_iter().map(|(a, h)| (a, h.to_ascii_lowercase())).collect());
+    }
+
+    pub fn add_stamp(&mut self, id: FileId, mut stamp: Stamp) {
+        stamp.hash = stamp.hash.to_ascii_lowercase();
+        self.stamps.insert(id, stamp);
+    }
+
+    pub fn document(&self, id: FileId) -> Option<(&[(HashAlgorithm, String)], DocumentMatch)> {
+        let hashes = self.documents.get(&id)?;
+        let mut any = false;
+        let mut verified = vec![];
+        for stamp in self.stamps.values().filter(|s| stamp_of(hashes, s)) {
+            any = true;
+            if stamp.verified {
+                verified.push(stamp.clone());
+            }
+        }
+        verified.sort_by_key(|s| s.stamp);
+        let state = if !verified.is_empty() {
+            DocumentMatch::Verified(verified)
+        } else if any {
+            DocumentMatch::Failed
+        } else {
+            DocumentMatch::Unstamped
+        };
+        return Some((hashes, state));
+    }
+
+    pub fn stamp(&self, id: FileId) -> Option<(&Stamp, StampMatch)> {
+        let stamp = self.stamps.get(&id)?;
+        let state = if self.documents.is_empty() {
+            StampMatch::Pending
+        } else if self.documents.values().any(|h| stamp_of(h, stamp)) {
+            StampMatch::Matched
+        } else {
+            StampMatch::Mismatch
+        };
+        return Some((stamp, state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{
+        TimeZone,
+        Utc,
+    };
+    use shared::hash::HashAlgorithm;
+    use super::{
+        DocumentMatch,
+        KeySource,
+        Matcher,
+        Stamp,
+        StampMatch,
+    };
+
+    fn doc(hash: &str) -> Vec<(HashAlgorithm, String)> {
+        return vec![(HashAlgorithm::Sha256, hash.to_string()), (HashAlgorithm::Blake3, format!("{}{}", hash, hash))];
+    }
+
+    fn stamp(hash: &str, day: u32, verified: bool) -> Stamp {
+        return Stamp {
+            algorithm: HashAlgorithm::Sha256,
+            hash: hash.to_string(),
+            salt: None,
+            stamp: Utc.with_ymd_and_hms(2023, 8, day, 0, 0, 0).unwrap(),
+            verified: verified,
+            key: KeySource::Fetched,
+            compromised: None,
+            attested: vec![],
+        };
+    }
+
+    #[test]
+    fn stamp_before_document() {
+        let mut m = Matcher::default();
+        let s = m.new_id();
+        m.add_stamp(s, stamp("aa", 1, true));
+        assert_eq!(m.stamp(s).unwrap().1, StampMatch::Pending);
+        let d = m.new_id();
+        m.add_document(d, doc("aa"));
+        assert_eq!(
+            m.document(d).unwrap().1,
+            DocumentMatch::Verified(vec![stamp("aa", 1, true)])
+        );
+        assert_eq!(m.stamp(s).unwrap().1, StampMatch::Matched);
+    }
+
+    #[test]
+    fn several_stamps() {
+        let mut m = Matcher::default();
+        let d = m.new_id();
+        m.add_document(d, doc("aa"));
+        for (day, verified) in [(5, true), (2, true), (3, false)] {
+            let s = m.new_id();
+            m.add_stamp(s, stamp("AA", day, verified));
+        }
+        assert_eq!(
+            m.document(d).unwrap().1,
+            DocumentMatch::Verified(vec![stamp("aa", 2, true), stamp("aa", 5, true)])
+        );
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut m = Matcher::default();
+        let d = m.new_id();
+        m.add_document(d, doc("aa"));
+        let s = m.new_id();
+        m.add_stamp(s, stamp("bb", 1, true));
+        assert_eq!(m.document(d).unwrap().1, DocumentMatch::Unstamped);
+        assert_eq!(m.stamp(s).unwrap().1, StampMatch::Mismatch);
+    }
+
+    #[test]
+    fn failed() {
+        let mut m = Matcher::default();
+        let d = m.new_id();
+        m.add_document(d, doc("aa"));
+        let s = m.new_id();
+        m.add_stamp(s, stamp("aa", 1, false));
+        assert_eq!(m.document(d).unwrap().1, DocumentMatch::Failed);
+    }
+
+    #[test]
+    fn same_name_documents() {
+        let mut m = Matcher::default();
+        let d1 = m.new_id();
+        m.add_document(d1, doc("aa"));
+        let d2 = m.new_id();
+        m.add_document(d2, doc("bb"));
+        let s = m.new_id();
+        m.add_stamp(s, stamp("bb", 1, true));
+        assert_eq!(m.document(d1).unwrap().1, DocumentMatch::Unstamped);
+        assert!(matches!(m.document(d2).unwrap().1, DocumentMatch::Verified(_)));
+    }
+
+    #[test]
+    fn algorithms() {
+        let mut m = Matcher::default();
+        let d = m.new_id();
+        m.add_document(d, doc("aa"));
+        let s1 = m.new_id();
+        m.add_stamp(s1, Stamp {
+            algorithm: HashAlgorithm::Blake3,
+            ..stamp("aaaa", 1, true)
+        });
+        let s2 = m.new_id();
+        m.add_stamp(s2, Stamp {
+            algorithm: HashAlgorithm::Sha512,
+            ..stamp("aa", 2, true)
+        });
+        assert_eq!(m.stamp(s1).unwrap().1, StampMatch::Matched);
+        assert_eq!(m.stamp(s2).unwrap().1, StampMatch::Mismatch);
+        assert_eq!(
+            m.document(d).unwrap().1,
+            DocumentMatch::Verified(vec![Stamp {
+                algorithm: HashAlgorithm::Blake3,
+                ..stamp("aaaa", 1, true)
+            }])
+        );
+    }
+
+    #[test]
+    fn blinded() {
+        let mut m = Matcher::default();
+        let d = m.new_id();
+        m.add_document(d, doc("aa"));
+        let blinded = Stamp {
+            salt: Some("01".repeat(32)),
+            ..stamp("8e016d8173b786fe1ae226458bb1d00c121bc257a53ac1689d43eeee4e97a241", 1, true)
+        };
+        let s1 = m.new_id();
+        m.add_stamp(s1, blinded.clone());
+        let s2 = m.new_id();
+        m.add_stamp(s2, Stamp {
+            salt: Some("02".repeat(32)),
+            ..blinded.clone()
+        });
+        assert_eq!(m.stamp(s1).unwrap().1, StampMatch::Matched);
+        assert_eq!(m.stamp(s2).unwrap().1, StampMatch::Mismatch);
+        assert_eq!(m.document(d).unwrap().1, DocumentMatch::Verified(vec![blinded]));
+    }
+}
@@ -0,0 +1,132 @@
+//! Checks stamps with pinned trust roots when possible, falling back to keys
+//! fetched from the server, and applies any compromise declared for the key.
+//! Loaded stamps are kept so they can be re-checked when the user adds more
+//! roots.
+use std::collections::HashMap;
+use gloo::console::console_dbg;
+use sequoia_openpgp::Cert;
+use shared::{
+    keys::{
+        parse_compromise,
+        SerialKeyMeta,
+    },
+    trust::TrustRoots,
+    verify::{
+        ParsedStamp,
+        VerifyError,
+    },
+};
+use crate::matching::{
+    FileId,
+    KeySource,
+    Stamp,
+};
+
+/// A seal cert from the server along with its published lifecycle.
+#[derive(Clone)]
+pub struct FetchedKey {
+    pub cert: Cert,
+    /// `None` if the server doesn't publish a lifecycle for the key
+    pub meta: Option<SerialKeyMeta>,
+}
+
+struct LoadedStamp {
+    parsed: ParsedStamp,
+    fetched: Option<FetchedKey>,
+    /// The unchecked compromise declaration published for the stamp's key, if
+    /// any
+    compromise: Option<Vec<u8>>,
+}
+
+pub struct Verifier {
+    roots: TrustRoots,
+    stamps: HashMap<FileId, LoadedStamp>,
+}
+
+impl Verifier {
+    pub fn new(roots: TrustRoots) -> Self {
+        return Verifier {
+            roots: roots,
+            stamps: HashMap::new(),
+        };
+    }
+
+    pub fn is_pinned(&self, stamp: &ParsedStamp) -> bool {
+        return self.roots.find(stamp).is_some();
+    }
+
+    fn check(&self, loaded: &LoadedStamp) -> Stamp {
+        let (result, key, cert) = if let Some(root) = self.roots.find(&loaded.parsed) {
+            (root.verify(&loaded.parsed), KeySource::Pinned, Some(&root.cert))
+        } else if let Some(fetched) = &loaded.fetched {
+            (loaded.parsed.verify(&[fetched.cert.clone()]).and_then(|verified| {
+                if let Some(meta) = &fetched.meta {
+                    meta.check(verified.stamp.stamp)?;
+                }
+                return Ok(verified);
+            }), KeySource::Fetched, Some(&fetched.cert))
+        } else {
+            (Err(VerifyError::UnknownIssuer(loaded.parsed.issuer.clone())), KeySource::Unknown, None)
+        };
+
+        // Only trust a declaration signed by the key itself
+        let mut compromise = None;
+        if let (Some(cert), Some(data)) = (cert, &loaded.compromise) {
+            match parse_compromise(data, cert) {
+                Ok(c) => compromise = Some(c),
+                Err(e) => {
+                    console_dbg!("Ignoring invalid compromise declaration", e.to_string());
+                },
+            }
+        }
+        let result = result.and_then(|verified| {
+            if let Some(compromise) = &compromise {
+                compromise.check(verified.stamp.stamp)?;
+            }
+            let attested = loaded.parsed.verify_roughtime(self.roots.roughtime_keys())?;
+            return Ok((verified, attested));
+        });
+        if let Err(e) = &result {
+            console_dbg!("Stamp failed verification", e.to_string());
+        }
+        return Stamp {
+            algorithm: loaded.parsed.stamp.algorithm,
+            hash: loaded.parsed.stamp.hash.clone(),
+            salt: loaded.parsed.salt.clone(),
+            stamp: loaded.parsed.stamp.stamp,
+            verified: result.is_ok(),
+            key: key,
+            compromised: compromise.map(|c| c.compromised),
+            attested: result
+                .map(|(_, attested)| attested.into_iter().map(|a| a.server).collect())
+                .unwrap_or_default(),
+        };
+    }
+
+    /// Check a newly loaded stamp.  `fetched` is the server's key for the
+    /// stamp's issuer, if the issuer isn't pinned and the server had one.
+    /// `compromise` is the compromise declaration the server published for the
+    /// issuer, if any.
+    pub fn add_stamp(
+        &mut self,
+        id: FileId,
+        parsed: ParsedStamp,
+        fetched: Option<FetchedKey>,
+        compromise: Option<Vec<u8>>,
+    ) -> Stamp {
+        let loaded = LoadedStamp {
+            parsed: parsed,
+            fetched: fetched,
+            compromise: compromise,
+        };
+        let out = self.check(&loaded);
+        self.stamps.insert(id, loaded);
+        return out;
+    }
+
+    /// Pin more keys, returning the new results for all loaded stamps.
+    pub fn add_roots(&mut self, roots: TrustRoots) -> Vec<(FileId, Stamp)> {
+        self.roots.extend(roots);
+        return self.stamps.iter().map(|(id, loaded)| (*id, self.check(loaded))).collect();
+    }
+}